@@ -0,0 +1,1634 @@
+use crate::encryption::{self, Keyring};
+use crate::incremental::ChangeTracker;
+use crate::source::SourceEntry;
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use walkdir::WalkDir;
+use zstd::{Decoder, Encoder};
+
+// zstd skippable frame used to carry the compression dictionary in front of the
+// archive. Standard zstd decoders skip it, but can't decode the frame after it
+// without the dictionary.
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A50;
+
+// Every zstd frame starts with this, so archives without it are plain tar
+const ZSTD_FRAME_MAGIC: u32 = 0xFD2FB528;
+const DICTIONARY_MARKER: &[u8] = b"zesty-dict";
+
+// Dictionary training limits (zstd's own CLI defaults to a 112 KiB dictionary)
+pub const MAX_DICTIONARY_SIZE: usize = 112_640;
+const MAX_SAMPLE_FILE_SIZE: u64 = 128 * 1024;
+const MAX_SAMPLES: usize = 4096;
+const MAX_SAMPLE_BYTES: usize = 16 * 1024 * 1024;
+
+// Prefix of the pax records holding extended attributes, as GNU tar writes them
+const PAX_XATTR_PREFIX: &[u8] = b"SCHILY.xattr.";
+
+// Progress while archiving: first after this many files, then each time the
+// count doubles, and at least this often while files keep going in
+const PROGRESS_FIRST_FILES: u64 = 10_000;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A tar builder that keeps count of the entries and bytes written, so the
+/// finished archive can be checked against it and its size recorded, of the
+/// files each exclude pattern left out, and of the files that couldn't be read.
+pub struct ArchiveBuilder<W: Write> {
+    tar: tar::Builder<W>,
+    entries: u64,
+    size: u64,
+    reproducible: bool,
+    changes: Option<ChangeTracker>,
+    excluded: BTreeMap<String, u64>,
+    unreadable: BTreeMap<String, String>,
+    skip_binary: Option<BinaryFilter>,
+    binary: u64,
+    xattrs: bool,
+    // Pax records for the entry being appended
+    pending_pax: Vec<u8>,
+    progress: Option<Progress>,
+}
+
+impl<W: Write> ArchiveBuilder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            tar: tar::Builder::new(writer),
+            entries: 0,
+            size: 0,
+            reproducible: false,
+            changes: None,
+            excluded: BTreeMap::new(),
+            unreadable: BTreeMap::new(),
+            skip_binary: None,
+            binary: 0,
+            xattrs: false,
+            pending_pax: Vec::new(),
+            progress: None,
+        }
+    }
+
+    /// Report the files and bytes archived so far as entries go in, for
+    /// trees big enough that the walk would otherwise be silent for minutes.
+    pub fn progress(mut self, progress: Option<Progress>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Record the extended attributes of files from disk (including POSIX
+    /// ACLs and SELinux contexts) in pax headers. Where xattrs aren't
+    /// supported this warns and does nothing.
+    pub fn xattrs(mut self, xattrs: bool) -> Self {
+        if xattrs && !XATTRS_SUPPORTED {
+            warn!("Extended attributes aren't supported on this platform and won't be backed up");
+        }
+        self.xattrs = xattrs && XATTRS_SUPPORTED;
+        self
+    }
+
+    /// Leave out files from sources that look binary. Files appended
+    /// directly (explicitly listed paths) are always kept.
+    pub fn skip_binary(mut self, filter: Option<BinaryFilter>) -> Self {
+        self.skip_binary = filter;
+        self
+    }
+
+    /// Files left out for looking binary.
+    pub fn binary_skipped(&self) -> u64 {
+        self.binary
+    }
+
+    /// Leave out files from disk that haven't changed since the previous backup,
+    /// recording the state of every file in the tracker.
+    pub fn track_changes(mut self, changes: Option<ChangeTracker>) -> Self {
+        self.changes = changes;
+        self
+    }
+
+    pub fn take_changes(&mut self) -> Option<ChangeTracker> {
+        self.changes.take()
+    }
+
+    /// Leave modification times out of the archive, so identical content
+    /// always produces identical bytes.
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        if reproducible {
+            self.tar.mode(tar::HeaderMode::Deterministic);
+        }
+        self.reproducible = reproducible;
+        self
+    }
+
+    /// Append generated contents (command output, dumps) as a regular file entry.
+    pub fn append_data(&mut self, archive_path: &str, data: &[u8]) -> io::Result<()> {
+        self.append_file_data(archive_path, data, 0o644, 0)
+    }
+
+    /// Append a file from disk, keeping its permissions and modification time.
+    pub fn append_file(&mut self, archive_path: &str, file: &mut fs::File) -> io::Result<()> {
+        if self.xattrs {
+            self.pending_pax = file_xattr_records(archive_path, file);
+        }
+        let metadata = file.metadata()?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let appended = self.append_reader(
+            archive_path,
+            file,
+            metadata.len(),
+            file_mode(&metadata),
+            mtime,
+            false,
+        );
+        self.pending_pax.clear();
+        appended
+    }
+
+    /// Append a file a source yielded.
+    pub fn append_entry(&mut self, entry: &mut SourceEntry) -> io::Result<()> {
+        if self.xattrs {
+            if let Some(ref path) = entry.path {
+                self.pending_pax = path_xattr_records(path);
+            }
+        }
+        let appended = self.append_reader(
+            &entry.archive_path,
+            &mut entry.reader,
+            entry.size,
+            entry.mode,
+            entry.mtime,
+            true,
+        );
+        // Left behind if the entry was skipped
+        self.pending_pax.clear();
+        appended
+    }
+
+    /// Append the contents of `reader`, a file of `size` bytes, unless change
+    /// tracking finds it unchanged or, with `filtered`, it looks binary.
+    fn append_reader(
+        &mut self,
+        archive_path: &str,
+        reader: &mut dyn Read,
+        size: u64,
+        mode: u32,
+        mtime: u64,
+        filtered: bool,
+    ) -> io::Result<()> {
+        let archive_path = &entry_path(archive_path);
+        // In mtime mode unchanged files are skipped without reading them
+        if let Some(ref mut changes) = self.changes {
+            if !changes.needs_contents() && !changes.record(archive_path, size, mtime, &[]) {
+                return Ok(());
+            }
+        }
+
+        // An unreadable file is left out, and out of the change state so the
+        // next backup tries again, rather than failing the whole backup
+        let mut contents = Vec::new();
+        if let Err(e) = reader.read_to_end(&mut contents) {
+            if let Some(ref mut changes) = self.changes {
+                changes.forget(archive_path);
+            }
+            self.record_unreadable(archive_path, &e);
+            return Ok(());
+        }
+
+        if let Some(filter) = self.skip_binary.filter(|_| filtered) {
+            if filter.is_binary(&contents) {
+                info!("Skipping binary file {}", archive_path);
+                if let Some(ref mut changes) = self.changes {
+                    changes.forget(archive_path);
+                }
+                self.binary += 1;
+                return Ok(());
+            }
+        }
+
+        if let Some(ref mut changes) = self.changes {
+            if changes.needs_contents() && !changes.record(archive_path, size, mtime, &contents) {
+                return Ok(());
+            }
+        }
+        self.append_file_data(archive_path, &contents, mode, mtime)
+    }
+
+    /// Append file contents with the given permissions and modification time.
+    pub fn append_file_data(
+        &mut self,
+        archive_path: &str,
+        data: &[u8],
+        mode: u32,
+        mtime: u64,
+    ) -> io::Result<()> {
+        self.append_stream(archive_path, &mut &data[..], data.len() as u64, mode, mtime)
+    }
+
+    /// Append the `size` bytes `reader` yields, copied through without
+    /// holding them in memory.
+    pub fn append_stream(
+        &mut self,
+        archive_path: &str,
+        reader: &mut dyn Read,
+        size: u64,
+        mode: u32,
+        mtime: u64,
+    ) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(mode);
+        if !self.reproducible {
+            header.set_mtime(mtime);
+        }
+        self.append_pending_pax()?;
+        self.tar
+            .append_data(&mut header, entry_path(archive_path).as_ref(), reader)?;
+        self.entries += 1;
+        self.size += size;
+        self.report_progress();
+        Ok(())
+    }
+
+    /// Append a file from disk, taking its metadata from the filesystem.
+    pub fn append_path_with_name(&mut self, path: &Path, archive_path: &str) -> io::Result<()> {
+        if self.xattrs {
+            self.pending_pax = path_xattr_records(path);
+            self.append_pending_pax()?;
+        }
+        self.tar
+            .append_path_with_name(path, entry_path(archive_path).as_ref())?;
+        self.entries += 1;
+        self.size +=
+            fs::symlink_metadata(path).map_or(0, |m| if m.is_file() { m.len() } else { 0 });
+        self.report_progress();
+        Ok(())
+    }
+
+    fn report_progress(&mut self) {
+        if let Some(ref mut progress) = self.progress {
+            progress.update(self.entries, self.size);
+        }
+    }
+
+    /// Write the pending pax records, if any, as an extended header for the
+    /// entry appended next.
+    fn append_pending_pax(&mut self) -> io::Result<()> {
+        if self.pending_pax.is_empty() {
+            return Ok(());
+        }
+        let records = std::mem::take(&mut self.pending_pax);
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::XHeader);
+        header.set_path("PaxHeader")?;
+        header.set_size(records.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        self.tar.append(&header, &records[..])
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.tar.get_mut()
+    }
+
+    /// Add the files a source left out per exclude pattern, keeping patterns
+    /// that never matched with a count of zero.
+    pub fn merge_excluded(&mut self, excluded: &BTreeMap<String, u64>) {
+        for (pattern, count) in excluded {
+            *self.excluded.entry(pattern.clone()).or_insert(0) += count;
+        }
+    }
+
+    /// Files left out per exclude pattern.
+    pub fn excluded(&self) -> &BTreeMap<String, u64> {
+        &self.excluded
+    }
+
+    /// Note a file that should have been archived but couldn't be read.
+    pub fn record_unreadable(&mut self, archive_path: &str, error: &io::Error) {
+        warn!("Skipping unreadable file {}: {}", archive_path, error);
+        self.unreadable
+            .insert(archive_path.to_string(), error.to_string());
+    }
+
+    /// Files that couldn't be read, with the error for each.
+    pub fn unreadable(&self) -> &BTreeMap<String, String> {
+        &self.unreadable
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Total size of the file contents written, before compression.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Write the tar trailer and hand back the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.tar.into_inner()
+    }
+}
+
+/// Throttled progress reports for an archive being written: first after a
+/// number of files, then each time the count doubles, and in between no more
+/// often than once per interval.
+pub struct Progress {
+    next: u64,
+    interval: Duration,
+    last: Instant,
+    report: Box<dyn FnMut(u64, u64) + Send>,
+}
+
+impl Progress {
+    /// Call `report` with the files and bytes archived so far.
+    pub fn new(
+        first: u64,
+        interval: Duration,
+        report: impl FnMut(u64, u64) + Send + 'static,
+    ) -> Self {
+        Self {
+            next: first.max(1),
+            interval,
+            last: Instant::now(),
+            report: Box::new(report),
+        }
+    }
+
+    /// Progress logged at info level, with the default cadence.
+    pub fn logged() -> Self {
+        Self::new(PROGRESS_FIRST_FILES, PROGRESS_INTERVAL, |files, size| {
+            info!(
+                "Archived {} files, {:.2} MB so far",
+                files,
+                size as f64 / 1_048_576.0
+            )
+        })
+    }
+
+    fn update(&mut self, files: u64, size: u64) {
+        if files < self.next && self.last.elapsed() < self.interval {
+            return;
+        }
+        (self.report)(files, size);
+        self.last = Instant::now();
+        while self.next <= files {
+            self.next *= 2;
+        }
+    }
+}
+
+/// Append `key=value` to `records` as a pax record, which starts with its
+/// own length in decimal.
+fn pax_record(records: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    let rest = key.len() + value.len() + 3; // ' ', '=' and '\n'
+    let mut len = rest + rest.to_string().len();
+    if len.to_string().len() > rest.to_string().len() {
+        len += 1;
+    }
+    records.extend_from_slice(format!("{} ", len).as_bytes());
+    records.extend_from_slice(key);
+    records.push(b'=');
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+#[cfg(unix)]
+const XATTRS_SUPPORTED: bool = xattr::SUPPORTED_PLATFORM;
+#[cfg(not(unix))]
+const XATTRS_SUPPORTED: bool = false;
+
+/// The pax records for the extended attributes `list` names and `get`
+/// reads. A filesystem without xattrs has none; other errors are warned
+/// about and leave the file's attributes out.
+#[cfg(unix)]
+fn xattr_records(
+    what: &str,
+    list: io::Result<xattr::XAttrs>,
+    get: impl Fn(&std::ffi::OsStr) -> io::Result<Option<Vec<u8>>>,
+) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let not_supported =
+        |e: &io::Error| rustix::io::Errno::from_io_error(e) == Some(rustix::io::Errno::NOTSUP);
+    let mut records = Vec::new();
+    let names = match list {
+        Ok(names) => names,
+        Err(e) if not_supported(&e) => return records,
+        Err(e) => {
+            warn!("Failed to list extended attributes of {}: {}", what, e);
+            return records;
+        }
+    };
+    for name in names {
+        match get(&name) {
+            Ok(Some(value)) => {
+                let key = [PAX_XATTR_PREFIX, name.as_bytes()].concat();
+                pax_record(&mut records, &key, &value);
+            }
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Failed to read extended attribute {} of {}: {}",
+                name.to_string_lossy(),
+                what,
+                e
+            ),
+        }
+    }
+    records
+}
+
+#[cfg(unix)]
+fn path_xattr_records(path: &Path) -> Vec<u8> {
+    xattr_records(&path.display().to_string(), xattr::list(path), |name| {
+        xattr::get(path, name)
+    })
+}
+
+#[cfg(unix)]
+fn file_xattr_records(archive_path: &str, file: &fs::File) -> Vec<u8> {
+    use xattr::FileExt;
+    xattr_records(archive_path, file.list_xattr(), |name| file.get_xattr(name))
+}
+
+#[cfg(not(unix))]
+fn path_xattr_records(_path: &Path) -> Vec<u8> {
+    Vec::new()
+}
+
+#[cfg(not(unix))]
+fn file_xattr_records(_archive_path: &str, _file: &fs::File) -> Vec<u8> {
+    Vec::new()
+}
+
+/// The extended attributes recorded for `entry`, as name/value pairs.
+pub fn entry_xattrs<R: Read>(entry: &mut tar::Entry<'_, R>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let Ok(Some(extensions)) = entry.pax_extensions() else {
+        return Vec::new();
+    };
+    extensions
+        .filter_map(|extension| extension.ok())
+        .filter_map(|extension| {
+            let name = extension.key_bytes().strip_prefix(PAX_XATTR_PREFIX)?;
+            Some((name.to_vec(), extension.value_bytes().to_vec()))
+        })
+        .collect()
+}
+
+/// Set the extended attributes of a restored file. One that can't be set,
+/// such as `security.*` without privileges, is warned about and skipped.
+#[cfg(unix)]
+pub fn restore_xattrs(path: &Path, xattrs: &[(Vec<u8>, Vec<u8>)]) {
+    use std::os::unix::ffi::OsStrExt;
+
+    for (name, value) in xattrs {
+        let name = std::ffi::OsStr::from_bytes(name);
+        if let Err(e) = xattr::set(path, name, value) {
+            warn!(
+                "Failed to restore extended attribute {} of {}: {}",
+                name.to_string_lossy(),
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn restore_xattrs(path: &Path, _xattrs: &[(Vec<u8>, Vec<u8>)]) {
+    warn!(
+        "Extended attributes of {} aren't restored on this platform",
+        path.display()
+    );
+}
+
+/// The name an archive entry gets. Archives made on Windows use `/` and
+/// leave out the drive, so they restore anywhere; elsewhere `\` is an
+/// ordinary file name character and names are kept as they are.
+fn entry_path(archive_path: &str) -> std::borrow::Cow<'_, str> {
+    if cfg!(windows) {
+        portable_entry_path(archive_path).into()
+    } else {
+        archive_path.into()
+    }
+}
+
+/// A Windows path as a portable entry name: `\` separators become `/`, and
+/// verbatim (`\\?\`), UNC (`\\server\share`) and drive (`C:`) prefixes
+/// and leading separators are dropped.
+fn portable_entry_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let mut rest = path.strip_prefix("//?/").unwrap_or(&path);
+    let unc = if rest.len() < path.len() {
+        rest.strip_prefix("UNC/")
+    } else {
+        rest.strip_prefix("//")
+    };
+    if let Some(unc) = unc {
+        // Server and share name
+        rest = unc.splitn(3, '/').nth(2).unwrap_or_default();
+    }
+    let bytes = rest.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        rest = &rest[2..];
+    }
+    rest.trim_start_matches('/').to_string()
+}
+
+#[cfg(unix)]
+pub fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+/// Stream an archive through the decoder and tar reader, reading every entry to
+/// the end, and check it holds the expected number of entries.
+pub fn verify_archive(path: &Path, expected_entries: u64) -> Result<()> {
+    verify_entries(open_archive(path)?, expected_entries)
+}
+
+/// `verify_archive` for an archive held in memory.
+pub fn verify_data(data: &[u8], expected_entries: u64) -> Result<()> {
+    verify_entries(decompress(data)?, expected_entries)
+}
+
+fn verify_entries<R: Read>(reader: R, expected_entries: u64) -> Result<()> {
+    let entries = check_entries(reader)?.entries;
+    if entries != expected_entries {
+        return Err(anyhow::anyhow!(
+            "Archive verification failed: expected {} entries, found {}",
+            expected_entries,
+            entries
+        ));
+    }
+    Ok(())
+}
+
+/// Collect small files under `path` as dictionary training samples.
+pub fn collect_samples(path: &Path, exclude: &[String]) -> Vec<Vec<u8>> {
+    let mut samples = Vec::new();
+    let mut total = 0;
+
+    for entry in WalkDir::new(path).follow_links(false).into_iter().flatten() {
+        if samples.len() >= MAX_SAMPLES || total >= MAX_SAMPLE_BYTES {
+            break;
+        }
+        let entry_path = entry.path();
+        if exclude
+            .iter()
+            .any(|pattern| entry_path.to_string_lossy().contains(pattern.as_str()))
+        {
+            continue;
+        }
+        let small_file = entry
+            .metadata()
+            .map(|m| m.is_file() && m.len() > 0 && m.len() <= MAX_SAMPLE_FILE_SIZE)
+            .unwrap_or(false);
+        if small_file {
+            if let Ok(contents) = fs::read(entry_path) {
+                total += contents.len();
+                samples.push(contents);
+            }
+        }
+    }
+
+    samples
+}
+
+/// Train a zstd dictionary from samples. The dictionary size is capped relative
+/// to the amount of sample data, since zstd refuses to train on too little input.
+pub fn train_dictionary(samples: &[Vec<u8>], max_size: usize) -> Result<Vec<u8>> {
+    let total: usize = samples.iter().map(|s| s.len()).sum();
+    let max_size = max_size.min(total / 10).max(256);
+    zstd::dict::from_samples(samples, max_size).context("Failed to train zstd dictionary")
+}
+
+/// The largest zstd window, which bounds how far back a patch can refer.
+const MAX_WINDOW_LOG: u32 = if cfg!(target_pointer_width = "64") {
+    31
+} else {
+    30
+};
+
+/// Compress `target` against `base`, like `zstd --patch-from`: the patch only
+/// holds what `target` doesn't share with `base`, and takes `base` to decode
+/// (see `apply_patch`). Both are plain tar streams, held in memory.
+pub fn create_patch(base: &[u8], target: &[u8], level: i32) -> Result<Vec<u8>> {
+    // The window has to reach from the end of `target` back to the start of `base`
+    let span = (base.len() + target.len()).max(1) as u64;
+    let window_log = (u64::BITS - (span - 1).leading_zeros()).clamp(10, MAX_WINDOW_LOG);
+    let mut encoder = Encoder::with_ref_prefix(Vec::new(), level, base)
+        .context("Failed to create zstd patch encoder")?;
+    encoder.window_log(window_log)?;
+    encoder.long_distance_matching(true)?;
+    encoder
+        .write_all(target)
+        .context("Failed to compress patch")?;
+    encoder.finish().context("Failed to finish patch")
+}
+
+/// Rebuild the tar stream a patch from `create_patch` was made from.
+pub fn apply_patch(base: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder =
+        Decoder::with_ref_prefix(patch, base).context("Failed to create zstd patch decoder")?;
+    decoder.window_log_max(MAX_WINDOW_LOG)?;
+    let mut target = Vec::new();
+    decoder
+        .read_to_end(&mut target)
+        .context("Failed to apply patch")?;
+    Ok(target)
+}
+
+/// Create a zstd encoder, embedding the dictionary (if any) ahead of the compressed stream.
+pub fn new_encoder<W: Write>(
+    mut writer: W,
+    level: i32,
+    dictionary: Option<&[u8]>,
+) -> Result<Encoder<'static, W>> {
+    match dictionary {
+        Some(dict) => {
+            let frame_size = (DICTIONARY_MARKER.len() + dict.len()) as u32;
+            writer.write_all(&SKIPPABLE_FRAME_MAGIC.to_le_bytes())?;
+            writer.write_all(&frame_size.to_le_bytes())?;
+            writer.write_all(DICTIONARY_MARKER)?;
+            writer.write_all(dict)?;
+            checksummed(
+                Encoder::with_dictionary(writer, level, dict)
+                    .context("Failed to create zstd encoder with dictionary")?,
+            )
+        }
+        None => checksummed(Encoder::new(writer, level).context("Failed to create zstd encoder")?),
+    }
+}
+
+/// End every frame with a checksum of its content, so reading an archive
+/// through also detects corruption the tar headers can't show.
+fn checksummed<W: Write>(mut encoder: Encoder<'static, W>) -> Result<Encoder<'static, W>> {
+    encoder
+        .include_checksum(true)
+        .context("Failed to enable zstd checksums")?;
+    Ok(encoder)
+}
+
+/// How `skip_binary` decides a file is binary, from a sample of its start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BinaryDetection {
+    /// The sample contains a NUL byte, as text files in any ASCII-compatible
+    /// encoding don't
+    #[default]
+    NullBytes,
+    /// The sample isn't UTF-8 text (or contains a NUL byte); stricter, also
+    /// skipping Latin-1 and UTF-16 text
+    NonUtf8,
+}
+
+/// `skip_binary`: judge files by their first `sample_size` bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BinaryFilter {
+    pub sample_size: usize,
+    pub detection: BinaryDetection,
+}
+
+impl BinaryFilter {
+    pub fn is_binary(&self, contents: &[u8]) -> bool {
+        let sample = &contents[..contents.len().min(self.sample_size)];
+        if sample.contains(&0) {
+            return true;
+        }
+        match self.detection {
+            BinaryDetection::NullBytes => false,
+            // A character cut off by the end of the sample is fine
+            BinaryDetection::NonUtf8 => std::str::from_utf8(sample)
+                .is_err_and(|e| e.error_len().is_some() || sample.len() == contents.len()),
+        }
+    }
+}
+
+/// How archives are compressed (`compression_format`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, JsonSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionFormat {
+    /// zstd-compressed tar (`.tar.zst`)
+    #[default]
+    #[serde(alias = "zstd")]
+    Zst,
+    /// Plain tar (`.tar`), for data that's already compressed
+    #[serde(rename = "none")]
+    #[value(name = "none")]
+    Plain,
+}
+
+impl CompressionFormat {
+    /// The archive file extension, without the leading dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            CompressionFormat::Zst => "tar.zst",
+            CompressionFormat::Plain => "tar",
+        }
+    }
+}
+
+/// Where an archive's tar stream goes: through zstd, or for a plain tar
+/// straight to the underlying writer with no encoder in between. An adaptive
+/// writer holds back the start of the stream until it has seen how well it
+/// compresses, and then picks one of the two for the whole archive.
+pub struct ArchiveWriter<W: Write> {
+    stream: Stream<W>,
+    sampled_ratio: Option<f64>,
+}
+
+enum Stream<W: Write> {
+    Zstd(ZstdWriter<W>),
+    Plain(W),
+    Sampling(Sampler<W>),
+}
+
+/// The start of an adaptive archive, buffered until `sample_size` bytes are in.
+struct Sampler<W: Write> {
+    writer: Option<W>,
+    buffer: Vec<u8>,
+    sample_size: usize,
+    min_ratio: f64,
+    level: i32,
+    dictionary: Option<Vec<u8>>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(
+        writer: W,
+        format: CompressionFormat,
+        level: i32,
+        dictionary: Option<&[u8]>,
+    ) -> Result<Self> {
+        let stream = match format {
+            CompressionFormat::Zst => Stream::Zstd(ZstdWriter::new(writer, level, dictionary)?),
+            CompressionFormat::Plain => Stream::Plain(writer),
+        };
+        Ok(Self {
+            stream,
+            sampled_ratio: None,
+        })
+    }
+
+    /// A writer that compresses the first `sample_size` bytes to measure the
+    /// ratio, and stores the archive uncompressed if it's below `min_ratio`.
+    pub fn adaptive(
+        writer: W,
+        level: i32,
+        dictionary: Option<&[u8]>,
+        sample_size: usize,
+        min_ratio: f64,
+    ) -> Self {
+        Self {
+            stream: Stream::Sampling(Sampler {
+                writer: Some(writer),
+                buffer: Vec::new(),
+                sample_size,
+                min_ratio,
+                level,
+                dictionary: dictionary.map(|d| d.to_vec()),
+            }),
+            sampled_ratio: None,
+        }
+    }
+
+    /// The format of the archive, once an adaptive writer has decided (see `settle`).
+    pub fn format(&self) -> Option<CompressionFormat> {
+        match self.stream {
+            Stream::Zstd(_) => Some(CompressionFormat::Zst),
+            Stream::Plain(_) => Some(CompressionFormat::Plain),
+            Stream::Sampling(_) => None,
+        }
+    }
+
+    /// The compression ratio an adaptive writer measured on its sample.
+    pub fn sampled_ratio(&self) -> Option<f64> {
+        self.sampled_ratio
+    }
+
+    /// Decide on the format now if an adaptive writer hasn't yet, because the
+    /// whole archive fit in the sample.
+    pub fn settle(&mut self) -> io::Result<()> {
+        let Stream::Sampling(sampler) = &mut self.stream else {
+            return Ok(());
+        };
+        let mut writer = sampler
+            .writer
+            .take()
+            .ok_or_else(|| io::Error::other("archive writer failed earlier"))?;
+        let buffer = std::mem::take(&mut sampler.buffer);
+        let compressed = zstd::bulk::compress(&buffer, sampler.level)?;
+        let ratio = buffer.len() as f64 / compressed.len().max(1) as f64;
+        self.sampled_ratio = Some(ratio);
+        self.stream = if ratio < sampler.min_ratio {
+            writer.write_all(&buffer)?;
+            Stream::Plain(writer)
+        } else {
+            let mut writer = ZstdWriter::new(writer, sampler.level, sampler.dictionary.as_deref())
+                .map_err(io::Error::other)?;
+            writer.write_all(&buffer)?;
+            Stream::Zstd(writer)
+        };
+        Ok(())
+    }
+
+    /// Compress everything written from here on at `level` (no-op for a plain tar).
+    pub fn set_level(&mut self, level: i32) -> Result<()> {
+        match &mut self.stream {
+            Stream::Zstd(writer) => writer.set_level(level),
+            Stream::Plain(_) => Ok(()),
+            // The sample goes out at the level in force when it's full
+            Stream::Sampling(sampler) => {
+                sampler.level = level;
+                Ok(())
+            }
+        }
+    }
+
+    /// Finish the compressed stream, if any, and hand back the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.settle()?;
+        match self.stream {
+            Stream::Zstd(writer) => writer.finish(),
+            Stream::Plain(writer) => Ok(writer),
+            Stream::Sampling(_) => unreachable!("settled above"),
+        }
+    }
+}
+
+impl<W: Write> Write for ArchiveWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.stream {
+            Stream::Zstd(writer) => writer.write(buf),
+            Stream::Plain(writer) => writer.write(buf),
+            Stream::Sampling(sampler) => {
+                sampler.buffer.extend_from_slice(buf);
+                if sampler.buffer.len() >= sampler.sample_size {
+                    self.settle()?;
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stream {
+            Stream::Zstd(writer) => writer.flush(),
+            Stream::Plain(writer) => writer.flush(),
+            Stream::Sampling(_) => Ok(()),
+        }
+    }
+}
+
+/// A zstd writer whose compression level can change part way through. zstd
+/// can't change level mid-frame, so each change ends the current frame and
+/// starts a new one; decoders read the concatenated frames as one stream.
+pub struct ZstdWriter<W: Write> {
+    encoder: Option<Encoder<'static, W>>,
+    dictionary: Option<Vec<u8>>,
+    level: i32,
+}
+
+impl<W: Write> ZstdWriter<W> {
+    pub fn new(writer: W, level: i32, dictionary: Option<&[u8]>) -> Result<Self> {
+        Ok(Self {
+            encoder: Some(new_encoder(writer, level, dictionary)?),
+            dictionary: dictionary.map(|d| d.to_vec()),
+            level,
+        })
+    }
+
+    /// Compress everything written from here on at `level`.
+    pub fn set_level(&mut self, level: i32) -> Result<()> {
+        if level == self.level {
+            return Ok(());
+        }
+        let writer = self
+            .encoder
+            .take()
+            .context("zstd writer is finished")?
+            .finish()
+            .context("Failed to finish zstd frame")?;
+        let encoder = match self.dictionary {
+            Some(ref dict) => Encoder::with_dictionary(writer, level, dict),
+            None => Encoder::new(writer, level),
+        }
+        .context("Failed to create zstd encoder")?;
+        self.encoder = Some(checksummed(encoder)?);
+        self.level = level;
+        Ok(())
+    }
+
+    /// Finish the last frame and hand back the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.encoder()?;
+        self.encoder.take().unwrap().finish()
+    }
+
+    fn encoder(&mut self) -> io::Result<&mut Encoder<'static, W>> {
+        self.encoder
+            .as_mut()
+            .ok_or_else(|| io::Error::other("zstd writer is finished"))
+    }
+}
+
+impl<W: Write> Write for ZstdWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder()?.flush()
+    }
+}
+
+/// Read the dictionary embedded at the start of an archive, if there is one.
+/// On return the reader is positioned at the start of the compressed stream.
+pub fn read_embedded_dictionary<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 8];
+    let mut read = 0;
+    while read < header.len() {
+        match reader.read(&mut header[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    if read < header.len()
+        || u32::from_le_bytes(header[..4].try_into().unwrap()) != SKIPPABLE_FRAME_MAGIC
+    {
+        return Ok(None);
+    }
+
+    let frame_size = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    let mut frame = vec![0u8; frame_size];
+    reader
+        .read_exact(&mut frame)
+        .context("Truncated dictionary frame")?;
+    match frame.strip_prefix(DICTIONARY_MARKER) {
+        Some(dict) => Ok(Some(dict.to_vec())),
+        None => Ok(None),
+    }
+}
+
+/// Returns true if the archive at `path` carries an embedded dictionary.
+#[cfg(test)]
+pub fn has_embedded_dictionary(path: &Path) -> Result<bool> {
+    let mut file = fs::File::open(path)
+        .with_context(|| format!("Failed to open archive: {}", path.display()))?;
+    Ok(read_embedded_dictionary(&mut file)?.is_some())
+}
+
+/// Open an unencrypted `.tar.zst` archive for reading (see `open_archive_with`).
+pub fn open_archive(path: &Path) -> Result<Box<dyn Read>> {
+    open_archive_with(path, &Keyring::default())
+}
+
+/// Open an archive for reading, decompressing with the embedded dictionary if present.
+/// A plain tar is read as is, a split archive through its volumes in order, and
+/// encrypted files are decrypted with the keyring.
+pub fn open_archive_with(path: &Path, keyring: &Keyring) -> Result<Box<dyn Read>> {
+    decompress(open_volumes(path, keyring)?)
+}
+
+/// Read an archive from a stream (such as a download in progress), decrypting
+/// and decompressing it as it's read. Nothing beyond the first few bytes is
+/// buffered, so the archive can be any size.
+pub fn open_stream<'a, R: Read + 'a>(reader: R, keyring: &Keyring) -> Result<Box<dyn Read + 'a>> {
+    let (header, reader) = peek(reader, encryption::AGE_MAGIC.len())?;
+    if encryption::has_age_header(&header) {
+        decompress(encryption::decrypt(reader, keyring)?)
+    } else if encryption::has_gpg_header(&header) {
+        decompress(encryption::decrypt_gpg_stream(reader, keyring)?)
+    } else {
+        decompress(reader)
+    }
+}
+
+/// Read the archive's tar stream to the end, reading every entry through to
+/// force the zstd checksums (and, for encrypted archives, the age tags) to be
+/// checked, and count what's in it. Memory use doesn't depend on the archive size.
+pub fn check_entries<R: Read>(reader: R) -> Result<ArchiveCheck> {
+    let mut archive = tar::Archive::new(reader);
+    let mut check = ArchiveCheck::default();
+    for entry in archive.entries().context("Failed to read archive")? {
+        let mut entry = entry.context("Corrupt archive entry")?;
+        check.size +=
+            io::copy(&mut entry, &mut io::sink()).context("Corrupt archive entry data")?;
+        check.entries += 1;
+    }
+    // zstd checks a frame's checksum at its end, past the tar trailer
+    io::copy(&mut archive.into_inner(), &mut io::sink()).context("Corrupt archive data")?;
+    Ok(check)
+}
+
+/// Check an encrypted archive's authentication tags, volume by volume, by
+/// decrypting it into a sink. Nothing is decompressed, so this is cheaper than
+/// `check_entries` but says nothing about the plaintext. Returns its size.
+pub fn authenticate(path: &Path, keyring: &Keyring) -> Result<u64> {
+    let files = archive_files(path);
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("Archive not found: {}", path.display()));
+    }
+    let mut size = 0;
+    for file in files {
+        if !encryption::is_encrypted(&file)? {
+            anyhow::bail!("{} is not encrypted", file.display());
+        }
+        size += encryption::authenticate_file(&file, keyring)?;
+    }
+    Ok(size)
+}
+
+/// `authenticate` for an archive read from a stream.
+pub fn authenticate_stream<R: Read>(reader: R, keyring: &Keyring) -> Result<u64> {
+    let (header, reader) = peek(reader, encryption::AGE_MAGIC.len())?;
+    let mut plaintext = if encryption::has_age_header(&header) {
+        encryption::decrypt(reader, keyring)?
+    } else if encryption::has_gpg_header(&header) {
+        encryption::decrypt_gpg_stream(reader, keyring)?
+    } else {
+        anyhow::bail!("Archive is not encrypted");
+    };
+    io::copy(&mut plaintext, &mut io::sink()).context("Failed to decrypt archive")
+}
+
+/// What a full read of an archive found.
+#[derive(Debug, Default, PartialEq)]
+pub struct ArchiveCheck {
+    pub entries: u64,
+    /// Total size of the entries' contents
+    pub size: u64,
+}
+
+/// Compare each file in an archive with the same path under `target`, by size
+/// and then SHA-1 of the contents, without writing anything. Directories only
+/// need to exist and symlinks to point at the same place; files under
+/// `target` that the archive doesn't have aren't reported.
+pub fn compare_entries<R: Read>(reader: R, target: &Path) -> Result<TreeComparison> {
+    use sha1::{Digest, Sha1};
+
+    let mut archive = tar::Archive::new(reader);
+    let mut comparison = TreeComparison::default();
+    for entry in archive.entries().context("Failed to read archive")? {
+        let mut entry = entry.context("Corrupt archive entry")?;
+        let name = entry.path().context("Invalid entry path")?.into_owned();
+        if !name.components().all(|c| matches!(c, Component::Normal(_))) {
+            anyhow::bail!("Archive entry outside the target: {}", name.display());
+        }
+        let on_disk = target.join(&name);
+        let name = name.to_string_lossy().to_string();
+        let Ok(meta) = fs::symlink_metadata(&on_disk) else {
+            comparison.missing.push(name);
+            continue;
+        };
+        let matches = match entry.header().entry_type() {
+            tar::EntryType::Directory => meta.is_dir(),
+            tar::EntryType::Link => meta.is_file(),
+            tar::EntryType::Symlink => {
+                meta.file_type().is_symlink()
+                    && fs::read_link(&on_disk).ok().as_deref() == entry.link_name()?.as_deref()
+            }
+            _ if !meta.is_file() || meta.len() != entry.size() => false,
+            _ => {
+                let mut archived = Sha1::new();
+                io::copy(&mut entry, &mut archived).context("Corrupt archive entry data")?;
+                let mut file = fs::File::open(&on_disk)
+                    .with_context(|| format!("Failed to read {}", on_disk.display()))?;
+                let mut live = Sha1::new();
+                io::copy(&mut file, &mut live)
+                    .with_context(|| format!("Failed to read {}", on_disk.display()))?;
+                archived.finalize() == live.finalize()
+            }
+        };
+        if matches {
+            comparison.matched += 1;
+        } else {
+            comparison.changed.push(name);
+        }
+    }
+    Ok(comparison)
+}
+
+/// How the files under a directory compare with an archive.
+#[derive(Debug, Default, PartialEq)]
+pub struct TreeComparison {
+    pub matched: u64,
+    /// Entries whose type, size, contents or link target differ
+    pub changed: Vec<String>,
+    /// Entries with nothing at their path
+    pub missing: Vec<String>,
+}
+
+/// Decompress an archive's (decrypted) bytes, picking zstd with or without an
+/// embedded dictionary, or none for a plain tar, from the first frame's magic.
+fn decompress<'a, R: Read + 'a>(reader: R) -> Result<Box<dyn Read + 'a>> {
+    let (header, mut reader) = peek(reader, 4)?;
+    let magic = match <[u8; 4]>::try_from(header.as_slice()) {
+        Ok(magic) => u32::from_le_bytes(magic),
+        // Too short to be either; let the zstd decoder report it
+        Err(_) => ZSTD_FRAME_MAGIC,
+    };
+    if magic == SKIPPABLE_FRAME_MAGIC {
+        // Any other skippable frame is consumed here too, which zstd would skip anyway
+        if let Some(dict) = read_embedded_dictionary(&mut reader)? {
+            let decoder = Decoder::with_dictionary(BufReader::new(reader), &dict)
+                .context("Failed to create zstd decoder with dictionary")?;
+            return Ok(Box::new(decoder));
+        }
+    } else if magic != ZSTD_FRAME_MAGIC && magic & 0xFFFF_FFF0 != SKIPPABLE_FRAME_MAGIC {
+        return Ok(Box::new(reader));
+    }
+    Ok(Box::new(
+        Decoder::new(reader).context("Failed to create zstd decoder")?,
+    ))
+}
+
+type Peeked<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+/// Read up to `len` bytes from the start of `reader`, returning them along
+/// with a reader that yields the whole stream again, those bytes included.
+fn peek<R: Read>(mut reader: R, len: usize) -> Result<(Vec<u8>, Peeked<R>)> {
+    let mut header = Vec::with_capacity(len);
+    reader
+        .by_ref()
+        .take(len as u64)
+        .read_to_end(&mut header)
+        .context("Failed to read archive")?;
+    Ok((header.clone(), io::Cursor::new(header).chain(reader)))
+}
+
+/// The raw bytes of an archive, concatenating the volumes of a split one.
+/// Volumes are encrypted one by one, so each is decrypted on its own.
+fn open_volumes(path: &Path, keyring: &Keyring) -> Result<Box<dyn Read>> {
+    let files = archive_files(path);
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("Archive not found: {}", path.display()));
+    }
+    let mut reader: Box<dyn Read> = Box::new(io::empty());
+    for file in files {
+        let volume = if encryption::is_encrypted(&file)? {
+            encryption::decrypt_file(&file, keyring)
+                .with_context(|| format!("Failed to decrypt {}", file.display()))?
+        } else {
+            Box::new(
+                fs::File::open(&file)
+                    .with_context(|| format!("Failed to open archive: {}", file.display()))?,
+            )
+        };
+        reader = Box::new(reader.chain(volume));
+    }
+    Ok(reader)
+}
+
+/// Volume `n` (from 1) of a split archive: `<archive>.001`, `<archive>.002`, ...
+pub fn volume_path(archive: &Path, n: u32) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(format!(".{:03}", n));
+    PathBuf::from(path)
+}
+
+/// The files holding an archive: the archive itself, or the volumes it was
+/// split into, in order. Empty if neither exists.
+pub fn archive_files(archive: &Path) -> Vec<PathBuf> {
+    if archive.exists() {
+        return vec![archive.to_path_buf()];
+    }
+    (1..)
+        .map(|n| volume_path(archive, n))
+        .take_while(|volume| volume.exists())
+        .collect()
+}
+
+/// Total size of an archive's files on disk.
+pub fn archive_size(archive: &Path) -> u64 {
+    archive_files(archive)
+        .iter()
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// SHA-1 of an archive as stored, over its volumes in order if it's split.
+pub fn archive_sha1(archive: &Path) -> Result<String> {
+    use sha1::{Digest, Sha1};
+
+    let mut hasher = Sha1::new();
+    for file in archive_files(archive) {
+        let mut reader =
+            fs::File::open(&file).with_context(|| format!("Failed to open {}", file.display()))?;
+        io::copy(&mut reader, &mut hasher)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Writes an archive to one file or, with a size limit, to numbered volumes
+/// (see `volume_path`) of at most `limit` bytes each; or, for small backups
+/// that never touch the disk, to memory.
+pub struct VolumeWriter {
+    path: PathBuf,
+    limit: Option<u64>,
+    sink: Sink,
+    written: u64,
+    volumes: u32,
+}
+
+enum Sink {
+    File(fs::File),
+    Memory(Vec<u8>),
+}
+
+impl VolumeWriter {
+    pub fn create(path: &Path, limit: Option<u64>) -> io::Result<Self> {
+        let (file, volumes) = match limit {
+            Some(_) => (fs::File::create(volume_path(path, 1))?, 1),
+            None => (fs::File::create(path)?, 0),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            limit,
+            sink: Sink::File(file),
+            written: 0,
+            volumes,
+        })
+    }
+
+    /// Write the archive to a buffer instead of a file (see `into_data`).
+    pub fn memory() -> Self {
+        Self {
+            path: PathBuf::new(),
+            limit: None,
+            sink: Sink::Memory(Vec::new()),
+            written: 0,
+            volumes: 0,
+        }
+    }
+
+    /// The archive written by a `memory` writer; `None` for a file.
+    pub fn into_data(self) -> Option<Vec<u8>> {
+        match self.sink {
+            Sink::File(_) => None,
+            Sink::Memory(data) => Some(data),
+        }
+    }
+
+    /// Flush the current file to disk (earlier volumes are synced as they fill up).
+    pub fn sync_all(&self) -> io::Result<()> {
+        match self.sink {
+            Sink::File(ref file) => file.sync_all(),
+            Sink::Memory(_) => Ok(()),
+        }
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let file = match self.sink {
+            Sink::File(ref mut file) => file,
+            Sink::Memory(ref mut data) => return data.write(buf),
+        };
+        let Some(limit) = self.limit else {
+            return file.write(buf);
+        };
+        if self.written >= limit {
+            file.sync_all()?;
+            self.volumes += 1;
+            *file = fs::File::create(volume_path(&self.path, self.volumes))?;
+            self.written = 0;
+        }
+        let room = (limit - self.written).min(buf.len() as u64) as usize;
+        let written = file.write(&buf[..room])?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.sink {
+            Sink::File(ref mut file) => file.flush(),
+            Sink::Memory(_) => Ok(()),
+        }
+    }
+}
+
+/// Parse a size like `4GB`, `700MiB` or `1048576`. KB/MB/GB/TB are decimal
+/// (`4GB` still fits FAT32's 4 GiB limit), KiB/MiB/GiB/TiB binary.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid size: {}", size))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        "TIB" => 1 << 40,
+        _ => return Err(anyhow::anyhow!("Invalid size unit: {}", size)),
+    };
+    number
+        .checked_mul(multiplier)
+        .with_context(|| format!("Size too large: {}", size))
+}
+
+/// One archive entry as reported by `audit`.
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub path: String,
+    pub entry_type: &'static str,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: u64,
+    pub warnings: Vec<String>,
+}
+
+// File names that usually hold credentials or keys
+const SECRET_NAMES: &[&str] = &[
+    ".env",
+    "id_rsa",
+    "id_ecdsa",
+    "id_ed25519",
+    ".netrc",
+    "credentials",
+];
+const SECRET_EXTENSIONS: &[&str] = &["pem", "key", "p12", "pfx"];
+
+/// Read every entry header in an archive without extracting anything.
+pub fn audit_archive(path: &Path, keyring: &Keyring) -> Result<Vec<AuditEntry>> {
+    let mut archive = tar::Archive::new(open_archive_with(path, keyring)?);
+    let mut entries = Vec::new();
+    for entry in archive.entries().context("Failed to read archive")? {
+        let entry = entry.context("Corrupt archive entry")?;
+        let header = entry.header();
+        let path = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let mode = header.mode().unwrap_or(0);
+        let entry_type = match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => "file",
+            tar::EntryType::Directory => "dir",
+            tar::EntryType::Symlink => "symlink",
+            tar::EntryType::Link => "hardlink",
+            _ => "other",
+        };
+
+        entries.push(AuditEntry {
+            warnings: audit_warnings(&path, mode),
+            path,
+            entry_type,
+            size: entry.size(),
+            mode,
+            mtime: header.mtime().unwrap_or(0),
+        });
+    }
+    Ok(entries)
+}
+
+/// Anything about an entry worth a second look: paths that would escape the
+/// restore directory, world-writable files and secrets others can read.
+pub fn audit_warnings(path: &str, mode: u32) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let entry_path = Path::new(path);
+    if entry_path.has_root() {
+        warnings.push("absolute path".to_string());
+    }
+    if entry_path.components().any(|c| c == Component::ParentDir) {
+        warnings.push("path contains '..'".to_string());
+    }
+    if mode & 0o002 != 0 {
+        warnings.push("world-writable".to_string());
+    }
+
+    let name = entry_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("");
+    let is_secret = SECRET_NAMES.contains(&name)
+        || name.starts_with(".env.")
+        || entry_path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| SECRET_EXTENSIONS.contains(&e));
+    if is_secret && mode & 0o044 != 0 {
+        warnings.push("secret readable by group/others".to_string());
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config_samples() -> Vec<Vec<u8>> {
+        (0..400)
+            .map(|i| {
+                format!(
+                    "[server]\nname = \"service-{i}\"\nport = {}\nworkers = {}\n\n[logging]\nlevel = \"info\"\npath = \"/var/log/service-{i}.log\"\n",
+                    8000 + i,
+                    i % 16
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    fn write_archive(path: &Path, samples: &[Vec<u8>], dictionary: Option<&[u8]>) -> u64 {
+        let file = fs::File::create(path).unwrap();
+        let mut tar = ArchiveBuilder::new(new_encoder(file, 3, dictionary).unwrap());
+        for (i, sample) in samples.iter().enumerate() {
+            tar.append_data(&format!("config/{}.toml", i), sample)
+                .unwrap();
+        }
+        let entries = tar.entries();
+        tar.finish().unwrap().finish().unwrap();
+        entries
+    }
+
+    fn read_archive(path: &Path) -> Vec<Vec<u8>> {
+        let mut archive = tar::Archive::new(open_archive(path).unwrap());
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut contents = Vec::new();
+                entry.unwrap().read_to_end(&mut contents).unwrap();
+                contents
+            })
+            .collect()
+    }
+
+    /// Test archives round-trip with and without an embedded dictionary
+    #[test]
+    fn test_dictionary_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let samples = config_samples();
+        let dict = train_dictionary(&samples, MAX_DICTIONARY_SIZE).unwrap();
+
+        let plain = temp_dir.path().join("plain.tar.zst");
+        write_archive(&plain, &samples, None);
+        assert!(!has_embedded_dictionary(&plain).unwrap());
+        assert_eq!(read_archive(&plain), samples);
+
+        let with_dict = temp_dir.path().join("dict.tar.zst");
+        write_archive(&with_dict, &samples, Some(&dict));
+        assert!(has_embedded_dictionary(&with_dict).unwrap());
+        assert_eq!(read_archive(&with_dict), samples);
+    }
+
+    /// Test level changes produce concatenated frames that read back as one stream
+    #[test]
+    fn test_zstd_writer_level_change() {
+        let temp_dir = TempDir::new().unwrap();
+        let samples = config_samples();
+        let dict = train_dictionary(&samples, MAX_DICTIONARY_SIZE).unwrap();
+
+        for dictionary in [None, Some(dict.as_slice())] {
+            let path = temp_dir.path().join("levels.tar.zst");
+            let file = fs::File::create(&path).unwrap();
+            let mut tar = ArchiveBuilder::new(ZstdWriter::new(file, 3, dictionary).unwrap());
+            for (i, sample) in samples.iter().enumerate() {
+                if i % 100 == 0 {
+                    tar.get_mut().set_level([3, 19][i / 100 % 2]).unwrap();
+                }
+                tar.append_data(&format!("config/{}.toml", i), sample)
+                    .unwrap();
+            }
+            tar.finish().unwrap().finish().unwrap();
+            assert_eq!(read_archive(&path), samples);
+        }
+    }
+
+    /// Test verification passes for a complete archive and catches a truncated one
+    #[test]
+    fn test_verify_detects_truncated_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("backup.tar.zst");
+        let entries = write_archive(&path, &config_samples(), None);
+        verify_archive(&path, entries).unwrap();
+        assert!(verify_archive(&path, entries + 1).is_err());
+
+        let len = fs::metadata(&path).unwrap().len();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len / 2).unwrap();
+        assert!(verify_archive(&path, entries).is_err());
+    }
+
+    /// Test sizes parse with decimal and binary units
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4GB").unwrap(), 4_000_000_000);
+        assert_eq!(parse_size("700 MiB").unwrap(), 700 << 20);
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("4PB").is_err());
+    }
+
+    /// Test audit flags escaping paths, world-writable files and exposed secrets
+    #[test]
+    fn test_audit_warnings() {
+        assert!(audit_warnings("project/src/main.rs", 0o644).is_empty());
+        assert_eq!(audit_warnings("/etc/passwd", 0o644), ["absolute path"]);
+        assert_eq!(
+            audit_warnings("project/../../root/.bashrc", 0o600),
+            ["path contains '..'"]
+        );
+        assert_eq!(audit_warnings("project/tmp/log", 0o666), ["world-writable"]);
+        assert_eq!(
+            audit_warnings("project/.env", 0o644),
+            ["secret readable by group/others"]
+        );
+        assert!(audit_warnings("project/certs/server.key", 0o600).is_empty());
+    }
+
+    /// Test Windows paths become entry names with forward slashes and no
+    /// drive or UNC prefix
+    #[test]
+    fn test_portable_entry_path() {
+        let cases = [
+            (r"project\app\src\main.rs", "project/app/src/main.rs"),
+            (r"C:\Users\me\notes.txt", "Users/me/notes.txt"),
+            (r"system\d:\data\db.sqlite", "system/d:/data/db.sqlite"),
+            (r"\\fileserver\share\docs\a.txt", "docs/a.txt"),
+            (r"\\?\C:\data\a.txt", "data/a.txt"),
+            (r"\\?\UNC\fileserver\share\b.txt", "b.txt"),
+            ("project/already/portable", "project/already/portable"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(portable_entry_path(path), expected, "{}", path);
+        }
+
+        // Only Windows rewrites names; elsewhere a backslash is part of the name
+        let mut builder = ArchiveBuilder::new(Vec::new());
+        builder.append_data(r"project\app\a.txt", b"a").unwrap();
+        let data = builder.finish().unwrap();
+        let mut archive = tar::Archive::new(data.as_slice());
+        let names: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect();
+        let expected = if cfg!(windows) {
+            "project/app/a.txt"
+        } else {
+            r"project\app\a.txt"
+        };
+        assert_eq!(names, [expected]);
+    }
+
+    /// Test progress is reported after the first files, then each time the
+    /// count doubles, and on every file once the interval allows it
+    #[test]
+    fn test_progress_cadence() {
+        use std::sync::{Arc, Mutex};
+
+        let archive = |progress: Progress| {
+            let mut builder = ArchiveBuilder::new(Vec::new()).progress(Some(progress));
+            for i in 0..100 {
+                builder
+                    .append_data(&format!("project/{}.txt", i), b"data")
+                    .unwrap();
+            }
+        };
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reports.clone();
+        archive(Progress::new(10, Duration::MAX, move |files, size| {
+            recorded.lock().unwrap().push((files, size))
+        }));
+        assert_eq!(
+            *reports.lock().unwrap(),
+            [(10, 40), (20, 80), (40, 160), (80, 320)]
+        );
+
+        let count = Arc::new(Mutex::new(0));
+        let counted = count.clone();
+        archive(Progress::new(1000, Duration::ZERO, move |_, _| {
+            *counted.lock().unwrap() += 1
+        }));
+        assert_eq!(*count.lock().unwrap(), 100);
+    }
+}
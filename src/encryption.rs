@@ -0,0 +1,676 @@
+use age::x25519;
+use anyhow::{anyhow, Context, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+// Every age file starts with this header line
+pub const AGE_MAGIC: &[u8] = b"age-encryption.org/v1";
+
+/// Archives are encrypted to every recipient, so any one of the matching
+/// identities can decrypt them. Rotating a key means adding the new recipient
+/// and keeping the old identity in `identity_files` until the backups
+/// encrypted to it have expired or been rekeyed.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct EncryptionConfig {
+    /// How new backups are encrypted (default: age)
+    #[serde(default)]
+    pub method: EncryptionMethod,
+    /// age public keys (`age1...`) new backups are encrypted to
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Files holding age secret keys (`AGE-SECRET-KEY-1...`), current and retired
+    #[serde(default)]
+    pub identity_files: Vec<String>,
+    /// With `method = "gpg"`, the key id, fingerprint or email new backups
+    /// are encrypted to
+    pub recipient: Option<String>,
+    /// GnuPG home directory to use instead of gpg's default
+    pub gpg_homedir: Option<String>,
+}
+
+/// How new backups are encrypted. Archives are read back either way,
+/// whatever the setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionMethod {
+    /// Built-in age encryption to `recipients`
+    #[default]
+    Age,
+    /// The external `gpg` to `recipient`, with keys managed in GnuPG
+    Gpg,
+}
+
+/// What new archives are encrypted with.
+pub enum Encryption {
+    Age(Vec<x25519::Recipient>),
+    Gpg(Gpg),
+}
+
+impl Encryption {
+    /// Encrypt the file at `path` in place.
+    pub fn encrypt_file(&self, path: &Path) -> Result<()> {
+        match self {
+            Encryption::Age(recipients) => encrypt_file(path, recipients),
+            Encryption::Gpg(gpg) => gpg.encrypt_file(path),
+        }
+    }
+
+    /// Encrypt an archive held in memory.
+    pub fn encrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Encryption::Age(recipients) => encrypt(data, Vec::new(), recipients),
+            Encryption::Gpg(gpg) => gpg.encrypt_bytes(data),
+        }
+    }
+}
+
+/// The age identities available for decrypting archives, and the GnuPG
+/// setup for decrypting gpg-encrypted ones (whose keys stay in GnuPG).
+#[derive(Default)]
+pub struct Keyring {
+    identities: Vec<x25519::Identity>,
+    gpg: Gpg,
+}
+
+impl Keyring {
+    /// Read every secret key from the given identity files, in the format
+    /// `age-keygen` writes: one key per line, `#` comments allowed.
+    pub fn load<P: AsRef<Path>>(files: &[P]) -> Result<Self> {
+        let mut identities = Vec::new();
+        for file in files {
+            let file = file.as_ref();
+            let contents = fs::read_to_string(file)
+                .with_context(|| format!("Failed to read identity file: {}", file.display()))?;
+            for line in contents.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let identity = line
+                    .parse::<x25519::Identity>()
+                    .map_err(|e| anyhow!("Invalid age identity in {}: {}", file.display(), e))?;
+                identities.push(identity);
+            }
+        }
+        Ok(Self {
+            identities,
+            gpg: Gpg::default(),
+        })
+    }
+
+    /// Decrypt gpg-encrypted archives with the keys in `homedir`.
+    pub fn with_gpg_homedir(mut self, homedir: Option<&str>) -> Self {
+        self.gpg.homedir = homedir.map(PathBuf::from);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.identities.is_empty()
+    }
+}
+
+/// The external `gpg`, encrypting to `recipient`.
+#[derive(Debug, Clone, Default)]
+pub struct Gpg {
+    recipient: Option<String>,
+    homedir: Option<PathBuf>,
+}
+
+impl Gpg {
+    pub fn new(recipient: &str, homedir: Option<&str>) -> Self {
+        Self {
+            recipient: Some(recipient.to_string()),
+            homedir: homedir.map(PathBuf::from),
+        }
+    }
+
+    /// Fail clearly up front if `gpg` can't be run.
+    pub fn check(&self) -> Result<()> {
+        self.command()
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(gpg_spawn_error)
+            .and_then(|status| {
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(anyhow!("gpg --version failed ({})", status))
+                }
+            })
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new("gpg");
+        command.arg("--quiet");
+        if let Some(ref homedir) = self.homedir {
+            command.arg("--homedir").arg(homedir);
+        }
+        command
+    }
+
+    /// `gpg --encrypt` to the recipient. The archive is already compressed,
+    /// so gpg's own compression is turned off.
+    fn encrypt_command(&self) -> Result<Command> {
+        let recipient = self
+            .recipient
+            .as_deref()
+            .context("No gpg recipient configured (encryption.recipient)")?;
+        let mut command = self.command();
+        command.args([
+            "--batch",
+            "--yes",
+            "--trust-model",
+            "always",
+            "--compress-algo",
+            "none",
+            "--encrypt",
+            "--recipient",
+            recipient,
+        ]);
+        Ok(command)
+    }
+
+    /// Encrypt the file at `path` in place, streaming it through gpg.
+    pub fn encrypt_file(&self, path: &Path) -> Result<()> {
+        replace_file(path, |input, output| {
+            let child = self
+                .encrypt_command()?
+                .stdin(Stdio::from(input.into_inner()))
+                .stdout(Stdio::from(output.try_clone()?))
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(gpg_spawn_error)?;
+            gpg_result("gpg --encrypt", child.wait_with_output()?)?;
+            Ok(output)
+        })
+    }
+
+    /// Encrypt `data` through gpg.
+    pub fn encrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut child = self
+            .encrypt_command()?
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(gpg_spawn_error)?;
+        let mut stdin = child.stdin.take().unwrap();
+        // Written from another thread so gpg's output is read as it comes
+        let output = std::thread::scope(|scope| {
+            scope.spawn(move || stdin.write_all(data));
+            child.wait_with_output()
+        })?;
+        gpg_result("gpg --encrypt", output).map(|output| output.stdout)
+    }
+
+    /// A reader over the plaintext of the gpg-encrypted `input`. gpg's exit
+    /// status is checked at the end of the stream, so a failed decryption or
+    /// integrity check surfaces as a read error.
+    fn decrypt(&self, input: Stdio) -> Result<Box<dyn Read>> {
+        let mut child = self
+            .command()
+            // Machine-readable status lines on stderr, whatever the locale
+            .args(["--status-fd", "2", "--decrypt"])
+            .stdin(input)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(gpg_spawn_error)?;
+        let stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        let errors = std::thread::spawn(move || {
+            let mut errors = String::new();
+            stderr.read_to_string(&mut errors).ok();
+            errors
+        });
+        Ok(Box::new(GpgReader {
+            child,
+            stdout,
+            errors: Some(errors),
+        }))
+    }
+}
+
+/// The error for a `gpg` that couldn't be started.
+fn gpg_spawn_error(e: io::Error) -> anyhow::Error {
+    if e.kind() == io::ErrorKind::NotFound {
+        anyhow!("gpg not found: install GnuPG or switch encryption.method back to \"age\"")
+    } else {
+        anyhow::Error::new(e).context("Failed to run gpg")
+    }
+}
+
+/// `output` if gpg succeeded, else an error with what it printed.
+fn gpg_result(what: &str, output: std::process::Output) -> Result<std::process::Output> {
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(anyhow!(
+            "{} failed ({}): {}",
+            what,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// The output of `gpg --decrypt`, failing at the end if gpg did.
+struct GpgReader {
+    child: Child,
+    stdout: ChildStdout,
+    errors: Option<std::thread::JoinHandle<String>>,
+}
+
+impl Read for GpgReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            if let Some(errors) = self.errors.take() {
+                let status = self.child.wait()?;
+                let errors = errors.join().unwrap_or_default();
+                if gpg_integrity_failed(&errors) {
+                    return Err(authentication_failed());
+                }
+                if !status.success() {
+                    let messages: Vec<&str> = errors
+                        .lines()
+                        .filter(|line| !line.starts_with(GPG_STATUS))
+                        .collect();
+                    return Err(io::Error::other(format!(
+                        "gpg --decrypt failed ({}): {}",
+                        status,
+                        messages.join("\n").trim()
+                    )));
+                }
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// The prefix of gpg's `--status-fd` lines.
+const GPG_STATUS: &str = "[GNUPG:] ";
+
+/// Whether gpg's status output shows the data failed its integrity check:
+/// the MDC didn't match, or decryption failed after the session key was
+/// recovered (a bad AEAD tag or truncated data), as opposed to a missing key.
+fn gpg_integrity_failed(errors: &str) -> bool {
+    let status: Vec<&str> = errors
+        .lines()
+        .filter_map(|line| line.strip_prefix(GPG_STATUS))
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    status.contains(&"BADMDC")
+        || (status.contains(&"DECRYPTION_FAILED") && status.contains(&"DECRYPTION_INFO"))
+}
+
+impl Drop for GpgReader {
+    fn drop(&mut self) {
+        // Stopped reading early; don't leave gpg behind
+        if self.errors.is_some() {
+            self.child.kill().ok();
+            self.child.wait().ok();
+        }
+    }
+}
+
+/// Parse age public keys, requiring at least one.
+pub fn parse_recipients(keys: &[String]) -> Result<Vec<x25519::Recipient>> {
+    if keys.is_empty() {
+        return Err(anyhow!("No encryption recipients configured"));
+    }
+    keys.iter()
+        .map(|key| {
+            key.parse::<x25519::Recipient>()
+                .map_err(|e| anyhow!("Invalid age recipient {}: {}", key, e))
+        })
+        .collect()
+}
+
+/// Returns true if the file at `path` is encrypted, with age or gpg.
+pub fn is_encrypted(path: &Path) -> Result<bool> {
+    let header = read_header(path)?;
+    Ok(has_age_header(&header) || has_gpg_header(&header))
+}
+
+/// The first bytes of the file at `path`, enough to tell how it's encrypted.
+fn read_header(path: &Path) -> Result<Vec<u8>> {
+    let file =
+        fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut header = Vec::with_capacity(AGE_MAGIC.len());
+    file.take(AGE_MAGIC.len() as u64)
+        .read_to_end(&mut header)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(header)
+}
+
+/// Returns true if `header`, the start of a file, is an age header.
+pub fn has_age_header(header: &[u8]) -> bool {
+    header.starts_with(AGE_MAGIC)
+}
+
+/// Returns true if `header`, the start of a file, is a binary OpenPGP
+/// message encrypted to a key or passphrase, as `gpg --encrypt` writes.
+/// A single byte isn't enough to go on (a plain tar whose first name starts
+/// with a UTF-8 letter like `é` begins with 0xc3), so the packet's length and
+/// version are checked too.
+pub fn has_gpg_header(header: &[u8]) -> bool {
+    let Some((&first, rest)) = header.split_first() else {
+        return false;
+    };
+    let (tag, length, body) = match first & 0xc0 {
+        // New format packet header: tag in the low 6 bits. Session key
+        // packets can't use partial lengths (224-254).
+        0xc0 => match rest {
+            [len @ 0..=191, body @ ..] => (first & 0x3f, *len as usize, body),
+            [a @ 192..=223, b, body @ ..] => (
+                first & 0x3f,
+                ((*a as usize - 192) << 8) + *b as usize + 192,
+                body,
+            ),
+            [255, a, b, c, d, body @ ..] => (
+                first & 0x3f,
+                u32::from_be_bytes([*a, *b, *c, *d]) as usize,
+                body,
+            ),
+            _ => return false,
+        },
+        // Old format packet header: tag in bits 2-5, length size in bits 0-1
+        0x80 => match (first & 0x03, rest) {
+            (0, [len, body @ ..]) => ((first >> 2) & 0x0f, *len as usize, body),
+            (1, [a, b, body @ ..]) => (
+                (first >> 2) & 0x0f,
+                u16::from_be_bytes([*a, *b]) as usize,
+                body,
+            ),
+            (2, [a, b, c, d, body @ ..]) => (
+                (first >> 2) & 0x0f,
+                u32::from_be_bytes([*a, *b, *c, *d]) as usize,
+                body,
+            ),
+            _ => return false,
+        },
+        _ => return false,
+    };
+    // A public-key (version 3 or 6) or passphrase (version 4 to 6) encrypted
+    // session key, which only ever holds a wrapped key
+    let version = body.first();
+    (4..=8192).contains(&length)
+        && match tag {
+            1 => matches!(version, Some(3 | 6)),
+            3 => matches!(version, Some(4..=6)),
+            _ => false,
+        }
+}
+
+/// Encrypted data that doesn't match its authentication tags: it was modified
+/// (or damaged) after it was encrypted. age checks a tag on every 64 KiB chunk
+/// and on the header, gpg on the whole message.
+#[derive(Debug)]
+pub struct AuthenticationFailed;
+
+impl std::fmt::Display for AuthenticationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(
+            "Encrypted archive failed authentication; it was modified after it was encrypted",
+        )
+    }
+}
+
+impl std::error::Error for AuthenticationFailed {}
+
+/// Whether `err` comes from encrypted data failing authentication, including
+/// when it surfaced as an I/O error from a reader further down.
+pub fn is_authentication_failure(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<AuthenticationFailed>().is_some()
+        || err.chain().any(|e| {
+            e.is::<AuthenticationFailed>()
+                || e.downcast_ref::<io::Error>()
+                    .and_then(|e| e.get_ref())
+                    .is_some_and(|e| e.is::<AuthenticationFailed>())
+        })
+}
+
+fn authentication_failed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, AuthenticationFailed)
+}
+
+/// The plaintext of an age stream, with a failed chunk tag (age reports
+/// those, and truncation, as `InvalidData`) turned into `AuthenticationFailed`.
+struct Authenticated<R>(R);
+
+impl<R: Read> Read for Authenticated<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData => authentication_failed(),
+            _ => e,
+        })
+    }
+}
+
+/// Decrypt the encrypted file at `path` into a sink, checking every
+/// authentication tag without decompressing or keeping anything. Returns the
+/// plaintext size.
+pub fn authenticate_file(path: &Path, keyring: &Keyring) -> Result<u64> {
+    io::copy(&mut decrypt_file(path, keyring)?, &mut io::sink())
+        .with_context(|| format!("Failed to decrypt {}", path.display()))
+}
+
+/// A reader over the plaintext of the encrypted file at `path`, decrypted
+/// with age or gpg depending on how it was encrypted.
+pub fn decrypt_file(path: &Path, keyring: &Keyring) -> Result<Box<dyn Read>> {
+    let file =
+        fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    if has_gpg_header(&read_header(path)?) {
+        keyring.gpg.decrypt(Stdio::from(file))
+    } else {
+        decrypt(BufReader::new(file), keyring)
+    }
+}
+
+/// A reader over the plaintext of a gpg-encrypted stream. gpg reads it from
+/// a private temporary copy, removed as soon as gpg has it open, so the
+/// stream is never held in memory.
+pub fn decrypt_gpg_stream<R: Read>(mut reader: R, keyring: &Keyring) -> Result<Box<dyn Read>> {
+    let mut spool = tempfile::Builder::new()
+        .prefix("zesty-backup-gpg-")
+        .tempfile()
+        .context("Failed to create temporary file")?;
+    io::copy(&mut reader, spool.as_file_mut()).context("Failed to read encrypted archive")?;
+    let file = spool
+        .reopen()
+        .with_context(|| format!("Failed to open {}", spool.path().display()))?;
+    keyring.gpg.decrypt(Stdio::from(file))
+}
+
+/// Stream `reader` into `writer`, encrypted to every recipient.
+pub fn encrypt<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    recipients: &[x25519::Recipient],
+) -> Result<W> {
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+            .context("Failed to set up encryption")?;
+    let mut output = encryptor
+        .wrap_output(writer)
+        .context("Failed to start encrypted stream")?;
+    io::copy(&mut reader, &mut output).context("Failed to encrypt archive")?;
+    output.finish().context("Failed to finish encrypted stream")
+}
+
+/// A reader over the plaintext of the age file `reader`, decrypted with
+/// whichever identity in the keyring matches.
+pub fn decrypt<'a, R: Read + 'a>(reader: R, keyring: &Keyring) -> Result<Box<dyn Read + 'a>> {
+    if keyring.is_empty() {
+        return Err(anyhow!(
+            "Archive is encrypted, but no age identities are configured (encryption.identity_files or --identity)"
+        ));
+    }
+    let decryptor = age::Decryptor::new(reader).context("Failed to read encrypted archive")?;
+    let plaintext = decryptor
+        .decrypt(keyring.identities.iter().map(|i| i as &dyn age::Identity))
+        .map_err(|e| match e {
+            age::DecryptError::NoMatchingKeys => {
+                anyhow!("None of the configured age identities can decrypt this archive")
+            }
+            age::DecryptError::InvalidMac | age::DecryptError::DecryptionFailed => {
+                anyhow::Error::new(e).context(AuthenticationFailed)
+            }
+            e => anyhow::Error::new(e).context("Failed to decrypt archive"),
+        })?;
+    Ok(Box::new(Authenticated(plaintext)))
+}
+
+/// Encrypt the file at `path` in place.
+pub fn encrypt_file(path: &Path, recipients: &[x25519::Recipient]) -> Result<()> {
+    replace_file(path, |input, output| encrypt(input, output, recipients))
+}
+
+/// Re-encrypt the age file at `path` in place to `recipients`, streaming the
+/// plaintext from the old encryption straight into the new one.
+pub fn rekey_file(path: &Path, keyring: &Keyring, recipients: &[x25519::Recipient]) -> Result<()> {
+    let header = read_header(path)?;
+    if has_gpg_header(&header) {
+        return Err(anyhow!(
+            "{} is encrypted with gpg; rekey only handles age",
+            path.display()
+        ));
+    }
+    if !has_age_header(&header) {
+        return Err(anyhow!("{} is not encrypted", path.display()));
+    }
+    replace_file(path, |input, output| {
+        encrypt(decrypt(input, keyring)?, output, recipients)
+    })
+}
+
+/// Write a transformed copy of `path` next to it and move it over the
+/// original once complete.
+fn replace_file<F>(path: &Path, transform: F) -> Result<()>
+where
+    F: FnOnce(BufReader<fs::File>, fs::File) -> Result<fs::File>,
+{
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".enc-tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let result = (|| {
+        let input =
+            fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let output = fs::File::create(&tmp_path)
+            .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+        transform(BufReader::new(input), output)?
+            .sync_all()
+            .context("Failed to flush encrypted archive")?;
+        fs::rename(&tmp_path, path).with_context(|| format!("Failed to replace {}", path.display()))
+    })();
+    if result.is_err() {
+        fs::remove_file(&tmp_path).ok();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+    use tempfile::TempDir;
+
+    fn identity_file(dir: &Path, name: &str, identities: &[&x25519::Identity]) -> PathBuf {
+        let path = dir.join(name);
+        let mut contents = String::from("# created: 2024-01-01T00:00:00Z\n");
+        for identity in identities {
+            contents.push_str(identity.to_string().expose_secret());
+            contents.push('\n');
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn read_all(reader: Result<Box<dyn Read + '_>>) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        reader?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Test a backup encrypted to key A stays readable after adding key B and
+    /// can be rekeyed to B alone
+    #[test]
+    fn test_rekey_to_new_identity() {
+        let temp_dir = TempDir::new().unwrap();
+        let key_a = x25519::Identity::generate();
+        let key_b = x25519::Identity::generate();
+        let archive = temp_dir.path().join("backup-full-1.tar.zst");
+        fs::write(&archive, b"archive data").unwrap();
+
+        encrypt_file(&archive, &[key_a.to_public()]).unwrap();
+        assert!(is_encrypted(&archive).unwrap());
+
+        // Key B joins the keyring; backups encrypted to A still decrypt
+        let keyring = Keyring::load(&[identity_file(
+            temp_dir.path(),
+            "keys.txt",
+            &[&key_b, &key_a],
+        )])
+        .unwrap();
+        let file = fs::File::open(&archive).unwrap();
+        assert_eq!(read_all(decrypt(file, &keyring)).unwrap(), b"archive data");
+
+        rekey_file(&archive, &keyring, &[key_b.to_public()]).unwrap();
+        let only_b = Keyring::load(&[identity_file(temp_dir.path(), "b.txt", &[&key_b])]).unwrap();
+        let only_a = Keyring::load(&[identity_file(temp_dir.path(), "a.txt", &[&key_a])]).unwrap();
+        let file = fs::File::open(&archive).unwrap();
+        assert_eq!(read_all(decrypt(file, &only_b)).unwrap(), b"archive data");
+        let file = fs::File::open(&archive).unwrap();
+        assert!(read_all(decrypt(file, &only_a)).is_err());
+        assert!(!temp_dir
+            .path()
+            .join("backup-full-1.tar.zst.enc-tmp")
+            .exists());
+    }
+
+    /// Test gpg's session key packets are recognised, but not a plain tar
+    /// whose first entry name starts with the same byte (0xc3 in `é`)
+    #[test]
+    fn test_gpg_header() {
+        // Public-key packets in old (RSA, ECDH) and new format
+        assert!(has_gpg_header(&[0x85, 0x01, 0x0c, 0x03, 0x1f]));
+        assert!(has_gpg_header(&[0x84, 0x5e, 0x03, 0x1f]));
+        assert!(has_gpg_header(&[0xc1, 0xc0, 0x4c, 0x03, 0x1f]));
+        // Passphrase packets, as gpg --symmetric writes them
+        assert!(has_gpg_header(&[0x8c, 0x0d, 0x04, 0x09]));
+        assert!(has_gpg_header(&[0xc3, 0x0d, 0x04, 0x09]));
+
+        let mut header = tar::Header::new_gnu();
+        header.set_path("été.sql").unwrap();
+        header.set_size(6);
+        header.set_cksum();
+        assert_eq!(header.as_bytes()[0], 0xc3);
+        assert!(!has_gpg_header(&header.as_bytes()[..AGE_MAGIC.len()]));
+        assert!(!has_gpg_header(&[]));
+        assert!(!has_gpg_header(&[0xc3]));
+    }
+
+    /// Test gpg's status lines tell a failed integrity check from a missing key
+    #[test]
+    fn test_gpg_integrity_failed() {
+        let tampered = "[GNUPG:] BEGIN_DECRYPTION\n[GNUPG:] DECRYPTION_INFO 2 9 0\n\
+                        gpg: WARNING: encrypted message has been manipulated!\n\
+                        [GNUPG:] BADMDC\n[GNUPG:] DECRYPTION_FAILED\n";
+        assert!(gpg_integrity_failed(tampered));
+        let truncated = "[GNUPG:] DECRYPTION_INFO 2 9 0\n[GNUPG:] DECRYPTION_FAILED\n\
+                         gpg: decryption failed: Invalid packet\n";
+        assert!(gpg_integrity_failed(truncated));
+        let no_key = "[GNUPG:] ENC_TO 46867DA1E5813C2B 1 0\n[GNUPG:] BEGIN_DECRYPTION\n\
+                      [GNUPG:] DECRYPTION_FAILED\ngpg: decryption failed: No secret key\n";
+        assert!(!gpg_integrity_failed(no_key));
+        // Warnings in another locale don't matter, only the status keywords
+        assert!(!gpg_integrity_failed("gpg: WARNUNG: manipulated\n"));
+    }
+}
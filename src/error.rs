@@ -0,0 +1,156 @@
+use std::io;
+use thiserror::Error;
+
+/// Errors returned by the backup operations, grouped by what failed so callers
+/// can react to the category instead of parsing messages. Each variant keeps
+/// the underlying error, with its context, as its source.
+#[derive(Debug, Error)]
+pub enum ZestyError {
+    /// The configuration is missing, unreadable or invalid.
+    #[error(transparent)]
+    Config(anyhow::Error),
+    /// Reading or writing local files failed.
+    #[error(transparent)]
+    Io(anyhow::Error),
+    /// A storage provider request failed.
+    #[error("{provider} storage request failed")]
+    Provider {
+        provider: String,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// Dumping a database failed.
+    #[error(transparent)]
+    Database(anyhow::Error),
+    /// An archive failed verification.
+    #[error(transparent)]
+    Verification(anyhow::Error),
+    /// An encrypted archive failed authentication: it was modified after it
+    /// was encrypted, as opposed to being unreadable once decrypted.
+    #[error(transparent)]
+    Authentication(anyhow::Error),
+    /// Part of the operation completed before another part failed.
+    #[error("{completed}, but {failed} failed")]
+    PartialFailure {
+        completed: String,
+        failed: String,
+        #[source]
+        source: Box<ZestyError>,
+    },
+    /// Anything not covered by a more specific category.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl ZestyError {
+    pub fn config(message: impl Into<String>) -> Self {
+        ZestyError::Config(anyhow::anyhow!(message.into()))
+    }
+
+    /// A failed archive check: `Authentication` if the encryption caught the
+    /// damage, `Verification` otherwise.
+    pub fn verification(source: anyhow::Error) -> Self {
+        if crate::encryption::is_authentication_failure(&source) {
+            ZestyError::Authentication(source)
+        } else {
+            ZestyError::Verification(source)
+        }
+    }
+
+    /// A failed request to `provider`. Errors the provider already categorized
+    /// (an unknown provider is a configuration error) keep their category.
+    pub fn provider(provider: &str, source: anyhow::Error) -> Self {
+        if source.chain().any(|e| e.is::<ZestyError>()) {
+            return source.into();
+        }
+        ZestyError::Provider {
+            provider: provider.to_string(),
+            source,
+        }
+    }
+
+    /// Process exit code for the CLI: 1 for uncategorized errors, then one
+    /// code per category.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ZestyError::Other(_) => 1,
+            ZestyError::Config(_) => 2,
+            ZestyError::Io(_) => 3,
+            ZestyError::Provider { .. } => 4,
+            ZestyError::Database(_) => 5,
+            ZestyError::Verification(_) => 6,
+            ZestyError::PartialFailure { .. } => 7,
+            ZestyError::Authentication(_) => 8,
+        }
+    }
+}
+
+impl From<anyhow::Error> for ZestyError {
+    /// Categorize an error from the internals. An error that was categorized
+    /// further down keeps its category, along with any context added on the
+    /// way up; otherwise failed file operations are `Io`.
+    fn from(err: anyhow::Error) -> Self {
+        if err.chain().next().is_some_and(|e| e.is::<ZestyError>()) {
+            return err.downcast().expect("checked above");
+        }
+        let category = err.chain().find_map(|e| e.downcast_ref::<ZestyError>());
+        match category {
+            Some(ZestyError::Config(_)) => ZestyError::Config(err),
+            Some(ZestyError::Io(_)) => ZestyError::Io(err),
+            Some(ZestyError::Provider { provider, .. }) => ZestyError::Provider {
+                provider: provider.clone(),
+                source: err,
+            },
+            Some(ZestyError::Database(_)) => ZestyError::Database(err),
+            Some(ZestyError::Verification(_)) => ZestyError::Verification(err),
+            Some(ZestyError::Authentication(_)) => ZestyError::Authentication(err),
+            Some(ZestyError::PartialFailure { .. }) | Some(ZestyError::Other(_)) => {
+                ZestyError::Other(err)
+            }
+            None if crate::encryption::is_authentication_failure(&err) => {
+                ZestyError::Authentication(err)
+            }
+            None if err.chain().any(|e| e.is::<io::Error>()) => ZestyError::Io(err),
+            None => ZestyError::Other(err),
+        }
+    }
+}
+
+impl From<io::Error> for ZestyError {
+    fn from(err: io::Error) -> Self {
+        ZestyError::Io(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    /// Test categories survive context added on the way up
+    #[test]
+    fn test_category_kept_through_context() {
+        let provider: anyhow::Error = ZestyError::Provider {
+            provider: "s3".to_string(),
+            source: anyhow::anyhow!("403 Forbidden"),
+        }
+        .into();
+        let err = ZestyError::from(provider.context("Failed to list remote backups"));
+        match &err {
+            ZestyError::Provider { provider, .. } => assert_eq!(provider, "s3"),
+            other => panic!("expected a provider error, got {:?}", other),
+        }
+        let message = format!("{:#}", anyhow::Error::from(err));
+        assert!(message.contains("Failed to list remote backups"));
+        assert!(message.contains("403 Forbidden"));
+
+        let io: anyhow::Result<()> = Err(io::Error::from(io::ErrorKind::NotFound).into());
+        let err = ZestyError::from(io.context("Failed to read backup directory").unwrap_err());
+        assert!(matches!(err, ZestyError::Io(_)));
+        assert_eq!(err.exit_code(), 3);
+
+        let other = ZestyError::from(anyhow::anyhow!("Backup not found"));
+        assert!(matches!(other, ZestyError::Other(_)));
+        assert_eq!(other.exit_code(), 1);
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// How an incremental backup decides whether a file changed since the last one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeDetection {
+    /// Size and modification time differ (fast)
+    Mtime,
+    /// Size or content hash differ, reading every file (ignores touched files)
+    Checksum,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileState {
+    pub size: u64,
+    pub mtime: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+/// The state of every file in the last backup, keyed by archive path, and the
+/// watermark of every source backed up in watermark mode, keyed by source path.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IncrementalManifest {
+    /// The backup that recorded this state, which the next incremental builds on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+    pub files: BTreeMap<String, FileState>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub watermarks: BTreeMap<String, Watermark>,
+}
+
+impl IncrementalManifest {
+    /// Load the manifest, or an empty one if no backup has written it yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read(path)
+            .with_context(|| format!("Failed to read incremental manifest: {}", path.display()))?;
+        serde_json::from_slice(&data)
+            .with_context(|| format!("Failed to parse incremental manifest: {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed to write incremental manifest: {}", path.display()))
+    }
+}
+
+/// Compares files against the previous manifest while building the next one.
+pub struct ChangeTracker {
+    detection: ChangeDetection,
+    previous: IncrementalManifest,
+    current: IncrementalManifest,
+    skipped: u64,
+}
+
+impl ChangeTracker {
+    /// An empty `previous` manifest (a full backup) treats every file as changed.
+    pub fn new(detection: ChangeDetection, previous: IncrementalManifest) -> Self {
+        Self {
+            detection,
+            previous,
+            current: IncrementalManifest::default(),
+            skipped: 0,
+        }
+    }
+
+    /// Whether `record` needs the file's contents to compare it.
+    pub fn needs_contents(&self) -> bool {
+        self.detection == ChangeDetection::Checksum
+    }
+
+    /// Record a file's state and return whether it changed since the previous
+    /// backup. `contents` is only hashed in checksum mode.
+    pub fn record(&mut self, archive_path: &str, size: u64, mtime: u64, contents: &[u8]) -> bool {
+        let state = FileState {
+            size,
+            mtime,
+            hash: self
+                .needs_contents()
+                .then(|| format!("{:x}", Sha1::digest(contents))),
+        };
+        let changed = match (self.previous.files.get(archive_path), self.detection) {
+            (None, _) => true,
+            (Some(previous), ChangeDetection::Mtime) => {
+                previous.size != size || previous.mtime != mtime
+            }
+            (Some(previous), ChangeDetection::Checksum) => {
+                previous.size != size || previous.hash != state.hash
+            }
+        };
+        if !changed {
+            self.skipped += 1;
+        }
+        self.current.files.insert(archive_path.to_string(), state);
+        changed
+    }
+
+    /// Files left out because they were unchanged.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Drop a recorded file that didn't make it into the archive after all,
+    /// so the next backup takes it as changed.
+    pub fn forget(&mut self, archive_path: &str) {
+        self.current.files.remove(archive_path);
+    }
+
+    pub fn into_manifest(self) -> IncrementalManifest {
+        self.current
+    }
+}
+
+/// How a source in watermark mode decides which files are new, for
+/// append-only directories (logs, WAL archives) where files never change once
+/// written and only files past the last backup need archiving.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WatermarkMode {
+    /// Modified after the newest file in the last backup
+    Mtime,
+    /// Path (relative to the source) sorts after the last one in the last backup
+    Name,
+}
+
+/// The furthest point a source has been backed up to.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Watermark {
+    /// Newest modification time seen, in nanoseconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+    /// Lexically last relative path seen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Keeps only the files past a source's watermark while advancing it.
+pub struct WatermarkFilter {
+    mode: WatermarkMode,
+    previous: Watermark,
+    current: Watermark,
+    skipped: u64,
+}
+
+impl WatermarkFilter {
+    /// No `previous` watermark (the first or a full backup) admits every file.
+    pub fn new(mode: WatermarkMode, previous: Option<Watermark>) -> Self {
+        let previous = previous.unwrap_or_default();
+        Self {
+            mode,
+            current: previous.clone(),
+            previous,
+            skipped: 0,
+        }
+    }
+
+    /// Whether a file is past the watermark and needs archiving.
+    pub fn admit(&mut self, relative_path: &str, mtime: u64) -> bool {
+        let new = match self.mode {
+            WatermarkMode::Mtime => self.previous.mtime.is_none_or(|seen| mtime > seen),
+            WatermarkMode::Name => self
+                .previous
+                .name
+                .as_deref()
+                .is_none_or(|seen| relative_path > seen),
+        };
+        match self.mode {
+            WatermarkMode::Mtime => self.current.mtime = self.current.mtime.max(Some(mtime)),
+            WatermarkMode::Name => {
+                if self.current.name.as_deref() < Some(relative_path) {
+                    self.current.name = Some(relative_path.to_string());
+                }
+            }
+        }
+        if !new {
+            self.skipped += 1;
+        }
+        new
+    }
+
+    /// Files left out because they were behind the watermark.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    pub fn into_watermark(self) -> Watermark {
+        self.current
+    }
+}
@@ -1,77 +1,684 @@
+use crate::error::ZestyError;
+use crate::retry::{self, CircuitBreaker};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use aws_sdk_s3::types::{Tag, Tagging};
 use aws_sdk_s3::{primitives::ByteStream, Client as S3Client, Config};
 use chrono::{DateTime, Utc};
 use futures::StreamExt;
-use std::path::Path;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use tracing::{info, warn};
 
 #[async_trait]
 pub trait StorageProvider: Send + Sync {
     async fn upload(&self, key: &str, file_path: &Path) -> Result<()>;
+    /// Upload `data` held in memory as `key`. The default writes it to a temp
+    /// file for `upload`; providers that take a request body send it as is.
+    async fn upload_bytes(&self, key: &str, data: &[u8]) -> Result<()> {
+        // Private to this user (0600) and removed when dropped
+        let mut temp_file = tempfile::Builder::new()
+            .prefix("zesty-backup-upload-")
+            .tempfile()
+            .context("Failed to create temp file")?;
+        std::io::Write::write_all(&mut temp_file, data).with_context(|| {
+            format!("Failed to write temp file: {}", temp_file.path().display())
+        })?;
+        self.upload(key, temp_file.path()).await
+    }
     async fn download(&self, key: &str, output_path: &Path) -> Result<()>;
     async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>>;
     async fn delete(&self, key: &str) -> Result<()>;
+    /// Upload only if nothing is stored under `key` yet, failing with
+    /// [`AlreadyExists`] otherwise. The default checks with `head` first, which
+    /// leaves a window for a concurrent writer; providers with a conditional
+    /// write do the check and the write in one request.
+    async fn upload_if_absent(&self, key: &str, file_path: &Path) -> Result<()> {
+        if self.head(key).await?.is_some() {
+            return Err(AlreadyExists(key.to_string()).into());
+        }
+        self.upload(key, file_path).await
+    }
+    /// Delete `key` only if it still has the ETag `etag`, failing with
+    /// [`Changed`] if it was replaced since. The default compares against
+    /// `head` first, which leaves a window for a concurrent writer; providers
+    /// with a conditional delete do the check and the delete in one request.
+    async fn delete_if_unchanged(&self, key: &str, etag: &str) -> Result<()> {
+        match self.head(key).await? {
+            Some(meta) if meta.etag.as_deref().is_some_and(|current| current != etag) => {
+                Err(Changed(key.to_string()).into())
+            }
+            _ => self.delete(key).await,
+        }
+    }
+    /// Read an object as a stream, without downloading it to a file first.
+    /// `Ok(None)` if the provider can't stream; callers then use `download`.
+    async fn open_stream(&self, _key: &str) -> Result<Option<ObjectStream>> {
+        Ok(None)
+    }
+    /// Copy an object server-side. Returns `Ok(false)` if the provider has no
+    /// server-side copy, in which case the caller has to upload the data again.
+    async fn copy(&self, _from_key: &str, _to_key: &str) -> Result<bool> {
+        Ok(false)
+    }
+    /// Attach tags to an object as provider-side metadata. Returns `Ok(false)` if
+    /// the provider has no object tagging; the uploaded manifest still has them.
+    async fn set_tags(&self, _key: &str, _tags: &[String]) -> Result<bool> {
+        Ok(false)
+    }
+    /// Metadata for a single object, or `None` if it doesn't exist. Providers
+    /// that store by file name look up the key's file name in their folder.
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>>;
+    /// Storage used (and the quota, where known). Providers without a quota API
+    /// fall back to summing the sizes of everything `list` returns.
+    async fn usage(&self) -> Result<StorageUsage> {
+        let items = self.list("").await?;
+        Ok(StorageUsage {
+            used: items.iter().map(|item| item.size).sum(),
+            total: None,
+        })
+    }
     #[allow(dead_code)]
     fn get_bucket(&self) -> &str;
 }
 
+/// A create-only upload found the key already taken.
+#[derive(Debug, thiserror::Error)]
+#[error("Object already exists: {0}")]
+pub struct AlreadyExists(pub String);
+
+/// Whether `error` (or anything it wraps) is an [`AlreadyExists`].
+pub fn is_already_exists(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<AlreadyExists>())
+}
+
+/// A conditional delete found the object replaced since it was listed.
+#[derive(Debug, thiserror::Error)]
+#[error("Object changed since it was listed: {0}")]
+pub struct Changed(pub String);
+
+/// Whether `error` (or anything it wraps) is a [`Changed`].
+pub fn is_changed(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<Changed>())
+}
+
+/// Files at least this large are uploaded in parts where the provider can,
+/// with progress saved between parts so an interrupted upload resumes.
+pub const MULTIPART_THRESHOLD: u64 = 256 * 1024 * 1024;
+
+/// Size of each part of a multipart upload (the last one may be smaller).
+pub const MULTIPART_PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Suffix of the file kept next to a file being uploaded in parts, recording
+/// the parts uploaded so far.
+pub const UPLOAD_STATE_SUFFIX: &str = ".upload-state";
+
+/// A multipart upload step failed in a way retrying won't fix, e.g. the
+/// upload was aborted or expired on the provider's side.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct PermanentUploadFailure(pub String);
+
+/// Whether `error` (or anything it wraps) is a [`PermanentUploadFailure`].
+pub fn is_permanent_upload_failure(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.is::<PermanentUploadFailure>())
+}
+
+/// An upload step failing with HTTP `status`; client errors won't go away on
+/// a retry, except timeouts, rate limits and expired credentials (401/403),
+/// which the next run re-authenticates for.
+fn upload_step_failure(status: u16, message: String) -> anyhow::Error {
+    if (400..500).contains(&status) && !matches!(status, 401 | 403 | 408 | 429) {
+        PermanentUploadFailure(message).into()
+    } else {
+        anyhow::anyhow!(message)
+    }
+}
+
+/// A part of a multipart upload, as the provider acknowledged it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadedPart {
+    pub number: u32,
+    /// S3's ETag or B2's SHA-1 for the part, needed to complete the upload
+    pub etag: String,
+}
+
+/// The steps of a provider's multipart upload, driven by [`resumable_upload`].
+#[async_trait]
+pub trait MultipartUpload: Send + Sync {
+    /// Start an upload of `key`, returning its upload id.
+    async fn start_multipart(&self, key: &str) -> Result<String>;
+    /// Upload part `number` (from 1), returning what completing the upload
+    /// needs to name it.
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: u32,
+        data: Vec<u8>,
+    ) -> Result<String>;
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<()>;
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> Result<()>;
+}
+
+/// Progress of a multipart upload, saved in `<file>.upload-state` after every
+/// part. It only applies while the file and the part size are unchanged.
+#[derive(Debug, Serialize, Deserialize)]
+struct UploadState {
+    key: String,
+    size: u64,
+    modified: Option<u64>,
+    part_size: u64,
+    upload_id: String,
+    parts: Vec<UploadedPart>,
+}
+
+impl UploadState {
+    fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write upload state: {}", path.display()))
+    }
+}
+
+/// The upload state file for `file_path`.
+pub fn upload_state_path(file_path: &Path) -> PathBuf {
+    let mut path = file_path.as_os_str().to_owned();
+    path.push(UPLOAD_STATE_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Upload `file_path` as `key` in parts of `part_size`, retrying each part
+/// per `policy`. Progress is saved next to the file, so when a part still
+/// fails the upload resumes from there the next time it is run. It starts
+/// over if the file changed since. Once completed, or after a failure
+/// retrying won't fix, the state file is removed (and on failure the
+/// provider's incomplete upload is aborted).
+pub async fn resumable_upload<M: MultipartUpload + ?Sized>(
+    uploader: &M,
+    key: &str,
+    file_path: &Path,
+    part_size: u64,
+    policy: &retry::RetryPolicy,
+) -> Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let metadata = std::fs::metadata(file_path)
+        .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|since| since.as_secs());
+    let part_count = size.div_ceil(part_size).max(1) as u32;
+    let state_path = upload_state_path(file_path);
+
+    let saved = std::fs::read(&state_path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<UploadState>(&data).ok());
+    let mut state = match saved {
+        Some(state)
+            if state.key == key
+                && state.size == size
+                && state.modified == modified
+                && state.part_size == part_size =>
+        {
+            info!(
+                "Resuming upload of {}: {} of {} parts already uploaded",
+                key,
+                state.parts.len(),
+                part_count
+            );
+            state
+        }
+        stale => {
+            if let Some(stale) = stale {
+                info!(
+                    "{} changed since its upload was interrupted; starting over",
+                    file_path.display()
+                );
+                if let Err(e) = uploader.abort_multipart(&stale.key, &stale.upload_id).await {
+                    warn!(
+                        "Failed to abort the earlier upload of {}: {:#}",
+                        stale.key, e
+                    );
+                }
+            }
+            let state = UploadState {
+                key: key.to_string(),
+                size,
+                modified,
+                part_size,
+                upload_id: uploader.start_multipart(key).await?,
+                parts: Vec::new(),
+            };
+            state.save(&state_path)?;
+            state
+        }
+    };
+
+    let mut file = std::fs::File::open(file_path)
+        .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+    for number in 1..=part_count {
+        if state.parts.iter().any(|part| part.number == number) {
+            continue;
+        }
+        let offset = (number - 1) as u64 * part_size;
+        let mut data = vec![0; part_size.min(size - offset) as usize];
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut data))
+            .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+
+        let mut attempt = 0;
+        let etag = loop {
+            attempt += 1;
+            match uploader
+                .upload_part(key, &state.upload_id, number, data.clone())
+                .await
+            {
+                Ok(etag) => break etag,
+                Err(e) if is_permanent_upload_failure(&e) => {
+                    abort_upload(uploader, &state, &state_path).await;
+                    return Err(e.context(format!("Failed to upload part {} of {}", number, key)));
+                }
+                Err(e) if attempt >= policy.max_attempts => {
+                    return Err(e.context(format!(
+                        "Upload of {} interrupted after {} of {} parts; run it again to resume",
+                        key,
+                        state.parts.len(),
+                        part_count
+                    )));
+                }
+                Err(e) => {
+                    let delay = policy.backoff(attempt);
+                    warn!(
+                        "Part {} of {} failed ({:#}), retrying in {:.1}s",
+                        number,
+                        key,
+                        e,
+                        delay.as_secs_f64()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        };
+        state.parts.push(UploadedPart { number, etag });
+        state.save(&state_path)?;
+        info!("Uploaded part {} of {} for {}", number, part_count, key);
+    }
+
+    state.parts.sort_by_key(|part| part.number);
+    if let Err(e) = uploader
+        .complete_multipart(key, &state.upload_id, &state.parts)
+        .await
+    {
+        abort_upload(uploader, &state, &state_path).await;
+        return Err(e.context(format!("Failed to complete the upload of {}", key)));
+    }
+    std::fs::remove_file(&state_path).ok();
+    Ok(())
+}
+
+/// Abort an upload that can't be finished, and forget it.
+async fn abort_upload<M: MultipartUpload + ?Sized>(
+    uploader: &M,
+    state: &UploadState,
+    state_path: &Path,
+) {
+    if let Err(e) = uploader.abort_multipart(&state.key, &state.upload_id).await {
+        warn!("Failed to abort the upload of {}: {:#}", state.key, e);
+    }
+    std::fs::remove_file(state_path).ok();
+}
+
+/// An object's contents, read as they arrive.
+pub type ObjectStream = std::pin::Pin<Box<dyn tokio::io::AsyncRead + Send>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectMeta {
+    pub size: u64,
+    pub last_modified: Option<DateTime<Utc>>,
+    pub etag: Option<String>, // ETag or content hash, where the provider has one
+}
+
+impl From<BackupItem> for ObjectMeta {
+    fn from(item: BackupItem) -> Self {
+        Self {
+            size: item.size,
+            last_modified: item.last_modified,
+            etag: item.etag,
+        }
+    }
+}
+
+/// The `Content-Type` to upload an object with, from its extension, so
+/// downloads through browsers and proxies are labeled correctly.
+pub fn content_type(key: &str) -> &'static str {
+    let extension = Path::new(key)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    match extension {
+        "zst" => "application/zstd",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "json" => "application/json",
+        "gpg" | "pgp" => "application/pgp-encrypted",
+        // Split archive volumes (.001) and anything else are opaque bytes
+        _ => "application/octet-stream",
+    }
+}
+
+/// A multipart upload part for a file, labeled with its content type.
+fn file_part(data: Vec<u8>, file_name: &str, key: &str) -> reqwest::multipart::Part {
+    reqwest::multipart::Part::bytes(data)
+        .file_name(file_name.to_string())
+        .mime_str(content_type(key))
+        .expect("content_type returns valid MIME types")
+}
+
+/// `PutOptions` carrying the object's content type and `[storage.metadata]`,
+/// for the object_store providers.
+fn put_options(key: &str, metadata: &BTreeMap<String, String>) -> object_store::PutOptions {
+    use object_store::{Attribute, Attributes};
+
+    let mut attributes = Attributes::from_iter([(Attribute::ContentType, content_type(key))]);
+    for (name, value) in metadata {
+        attributes.insert(
+            Attribute::Metadata(name.clone().into()),
+            value.clone().into(),
+        );
+    }
+    object_store::PutOptions {
+        attributes,
+        ..Default::default()
+    }
+}
+
+/// Percent-encode a `X-Bz-Info-*` value, keeping the characters B2 allows as is.
+fn b2_info_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => (b as char).to_string(),
+            b'.' | b'_' | b'-' | b'/' | b'~' | b'!' | b'$' | b'\'' | b'(' | b')' | b'*' | b';'
+            | b'=' | b':' | b'@' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageUsage {
+    pub used: u64,
+    pub total: Option<u64>,
+}
+
 #[derive(Debug, Clone)]
 pub struct BackupItem {
     pub key: String,
     pub size: u64,
     pub last_modified: Option<DateTime<Utc>>,
+    pub etag: Option<String>, // ETag or content hash from the listing, where the provider has one
 }
 
+/// S3 Transfer Acceleration endpoint; buckets are addressed as
+/// `<bucket>.s3-accelerate.amazonaws.com`.
+pub const S3_ACCELERATE_ENDPOINT: &str = "https://s3-accelerate.amazonaws.com";
+
 // S3-compatible provider (AWS S3, Contabo, DigitalOcean Spaces, Wasabi, etc.)
 pub struct S3Provider {
     client: S3Client,
+    // Downloads go through their own endpoint when `download_endpoint` is set
+    download_client: Option<S3Client>,
     bucket: String,
+    // Sent as `x-amz-meta-*` with every upload
+    metadata: std::collections::HashMap<String, String>,
 }
 
 impl S3Provider {
     pub async fn new(
         endpoint: &str,
+        download_endpoint: Option<&str>,
         region: &str,
         bucket: &str,
         access_key: &str,
         secret_key: &str,
+        network: &NetworkConfig,
     ) -> Result<Self> {
         use aws_credential_types::Credentials;
         let credentials = Credentials::new(access_key, secret_key, None, None, "zesty-backup");
 
-        let s3_config = Config::builder()
-            .endpoint_url(endpoint)
-            .region(aws_sdk_s3::config::Region::new(region.to_string()))
-            .credentials_provider(credentials)
-            .build();
-
-        let client = S3Client::from_conf(s3_config);
+        let client = |endpoint: &str| -> Result<S3Client> {
+            let mut s3_config = Config::builder()
+                .endpoint_url(endpoint)
+                .region(aws_sdk_s3::config::Region::new(region.to_string()))
+                .credentials_provider(credentials.clone());
+            s3_config = s3_config.http_client(network.aws_http_client(endpoint)?);
+            Ok(S3Client::from_conf(s3_config.build()))
+        };
 
         Ok(Self {
-            client,
+            client: client(endpoint)?,
+            download_client: download_endpoint
+                .filter(|download| !download.is_empty() && *download != endpoint)
+                .map(client)
+                .transpose()?,
             bucket: bucket.to_string(),
+            metadata: Default::default(),
         })
     }
-}
 
-#[async_trait]
-impl StorageProvider for S3Provider {
-    async fn upload(&self, key: &str, file_path: &Path) -> Result<()> {
+    /// Attach `metadata` to every uploaded object.
+    pub fn with_metadata(mut self, metadata: &BTreeMap<String, String>) -> Self {
+        self.metadata = metadata.clone().into_iter().collect();
+        self
+    }
+
+    /// A `PutObject` request for `key` with its content type and metadata.
+    fn put_request(
+        &self,
+        key: &str,
+    ) -> aws_sdk_s3::operation::put_object::builders::PutObjectFluentBuilder {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type(key))
+            .set_metadata((!self.metadata.is_empty()).then(|| self.metadata.clone()))
+    }
+
+    /// The client for reading objects back.
+    fn download_client(&self) -> &S3Client {
+        self.download_client.as_ref().unwrap_or(&self.client)
+    }
+
+    /// PUT `file_path` as `key`; with `create_only`, send `If-None-Match: *`
+    /// so S3 refuses (412) to replace an existing object.
+    async fn put(&self, key: &str, file_path: &Path, create_only: bool) -> Result<()> {
         info!("Uploading {} to S3...", key);
         let body = ByteStream::from_path(file_path)
             .await
             .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
 
+        let mut request = self.put_request(key).body(body);
+        if create_only {
+            request = request.if_none_match("*");
+        }
+        if let Err(e) = request.send().await {
+            let status = e.raw_response().map(|r| r.status().as_u16());
+            // 409 is a conflicting conditional write still in flight
+            if create_only && matches!(status, Some(412) | Some(409)) {
+                return Err(AlreadyExists(key.to_string()).into());
+            }
+            return Err(e).with_context(|| format!("Failed to upload to S3: {}", key));
+        }
+
+        info!("Successfully uploaded: {}", key);
+        Ok(())
+    }
+}
+
+/// An S3 request that failed, as a (possibly permanent) upload step failure.
+fn s3_upload_failure<E>(
+    error: aws_sdk_s3::error::SdkError<E, aws_sdk_s3::config::http::HttpResponse>,
+    message: String,
+) -> anyhow::Error
+where
+    E: std::error::Error + aws_sdk_s3::error::ProvideErrorMetadata + Send + Sync + 'static,
+{
+    use aws_sdk_s3::error::ProvideErrorMetadata;
+    let status = match error.code() {
+        Some("NoSuchUpload") => 404,
+        _ => error
+            .raw_response()
+            .map(|r| r.status().as_u16())
+            .unwrap_or_default(),
+    };
+    upload_step_failure(
+        status,
+        format!(
+            "{}: {}",
+            message,
+            aws_sdk_s3::error::DisplayErrorContext(&error)
+        ),
+    )
+}
+
+#[async_trait]
+impl MultipartUpload for S3Provider {
+    async fn start_multipart(&self, key: &str) -> Result<String> {
+        let response = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type(key))
+            .set_metadata((!self.metadata.is_empty()).then(|| self.metadata.clone()))
+            .send()
+            .await
+            .with_context(|| format!("Failed to start multipart upload to S3: {}", key))?;
+        response
+            .upload_id()
+            .map(str::to_string)
+            .context("S3 returned no upload id")
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: u32,
+        data: Vec<u8>,
+    ) -> Result<String> {
+        let response = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(number as i32)
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .map_err(|e| s3_upload_failure(e, format!("Failed to upload part {} to S3", number)))?;
+        response
+            .e_tag()
+            .map(str::to_string)
+            .context("S3 returned no ETag for the part")
+    }
+
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<()> {
+        use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+        let parts = parts
+            .iter()
+            .map(|part| {
+                CompletedPart::builder()
+                    .part_number(part.number as i32)
+                    .e_tag(&part.etag)
+                    .build()
+            })
+            .collect();
         self.client
-            .put_object()
+            .complete_multipart_upload()
             .bucket(&self.bucket)
             .key(key)
-            .body(body)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
             .send()
             .await
-            .with_context(|| format!("Failed to upload to S3: {}", key))?;
+            .with_context(|| format!("Failed to complete multipart upload to S3: {}", key))?;
+        Ok(())
+    }
+
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> Result<()> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .with_context(|| format!("Failed to abort multipart upload to S3: {}", key))?;
+        Ok(())
+    }
+}
+
+/// Whether `file_path` is large enough to upload in parts.
+fn wants_multipart(file_path: &Path) -> bool {
+    std::fs::metadata(file_path).is_ok_and(|m| m.len() >= MULTIPART_THRESHOLD)
+}
+
+#[async_trait]
+impl StorageProvider for S3Provider {
+    async fn upload(&self, key: &str, file_path: &Path) -> Result<()> {
+        if wants_multipart(file_path) {
+            info!("Uploading {} to S3 in parts...", key);
+            let policy = retry::RetryPolicy::default();
+            resumable_upload(self, key, file_path, MULTIPART_PART_SIZE, &policy).await?;
+            info!("Successfully uploaded: {}", key);
+            return Ok(());
+        }
+        self.put(key, file_path, false).await
+    }
+
+    async fn upload_if_absent(&self, key: &str, file_path: &Path) -> Result<()> {
+        // A multipart upload has no conditional write; check first instead
+        if wants_multipart(file_path) {
+            if self.head(key).await?.is_some() {
+                return Err(AlreadyExists(key.to_string()).into());
+            }
+            return self.upload(key, file_path).await;
+        }
+        self.put(key, file_path, true).await
+    }
 
+    async fn upload_bytes(&self, key: &str, data: &[u8]) -> Result<()> {
+        info!("Uploading {} to S3...", key);
+        self.put_request(key)
+            .body(ByteStream::from(data.to_vec()))
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload to S3: {}", key))?;
         info!("Successfully uploaded: {}", key);
         Ok(())
     }
@@ -79,7 +686,7 @@ impl StorageProvider for S3Provider {
     async fn download(&self, key: &str, output_path: &Path) -> Result<()> {
         info!("Downloading {} from S3...", key);
         let response = self
-            .client
+            .download_client()
             .get_object()
             .bucket(&self.bucket)
             .key(key)
@@ -102,45 +709,33 @@ impl StorageProvider for S3Provider {
         Ok(())
     }
 
-    async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
-        let mut items = Vec::new();
-        let mut continuation_token: Option<String> = None;
+    async fn open_stream(&self, key: &str) -> Result<Option<ObjectStream>> {
+        let response = self
+            .download_client()
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .context("Failed to download from S3")?;
+        Ok(Some(Box::pin(response.body.into_async_read())))
+    }
 
-        loop {
+    async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
+        s3_list_pages(|continuation_token| async move {
             let mut request = self
                 .client
                 .list_objects_v2()
                 .bucket(&self.bucket)
                 .prefix(prefix);
 
-            if let Some(token) = continuation_token.take() {
-                request = request.continuation_token(token.as_str());
-            }
-
-            let response = request.send().await.context("Failed to list S3 objects")?;
-
-            for obj in response.contents() {
-                if let Some(key) = obj.key() {
-                    let item = BackupItem {
-                        key: key.to_string(),
-                        size: obj.size().unwrap_or(0) as u64,
-                        last_modified: obj.last_modified().map(|dt| {
-                            // Convert AWS DateTime to chrono DateTime
-                            let secs = dt.secs();
-                            DateTime::from_timestamp(secs, 0).unwrap_or_else(Utc::now)
-                        }),
-                    };
-                    items.push(item);
-                }
-            }
-
-            continuation_token = response.next_continuation_token().map(|s| s.to_string());
-            if response.is_truncated() != Some(true) {
-                break;
+            if let Some(token) = continuation_token {
+                request = request.continuation_token(token);
             }
-        }
 
-        Ok(items)
+            request.send().await.context("Failed to list S3 objects")
+        })
+        .await
     }
 
     async fn delete(&self, key: &str) -> Result<()> {
@@ -155,6 +750,81 @@ impl StorageProvider for S3Provider {
         Ok(())
     }
 
+    async fn delete_if_unchanged(&self, key: &str, etag: &str) -> Result<()> {
+        let request = self
+            .client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .if_match(etag);
+        if let Err(e) = request.send().await {
+            let status = e.raw_response().map(|r| r.status().as_u16());
+            if status == Some(412) {
+                return Err(Changed(key.to_string()).into());
+            }
+            return Err(e).context("Failed to delete S3 object");
+        }
+        info!("Deleted from S3: {}", key);
+        Ok(())
+    }
+
+    async fn copy(&self, from_key: &str, to_key: &str) -> Result<bool> {
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .copy_source(format!("{}/{}", self.bucket, from_key))
+            .key(to_key)
+            .send()
+            .await
+            .with_context(|| format!("Failed to copy S3 object {} to {}", from_key, to_key))?;
+        Ok(true)
+    }
+
+    async fn set_tags(&self, key: &str, tags: &[String]) -> Result<bool> {
+        // S3 allows 10 tags per object; labels are stored as keys with empty values
+        if tags.len() > 10 {
+            warn!("S3 allows at most 10 object tags; only the first 10 are set");
+        }
+        let tag_set = tags
+            .iter()
+            .take(10)
+            .map(|tag| Tag::builder().key(tag).value("").build())
+            .collect::<Result<Vec<_>, _>>()?;
+        let tagging = Tagging::builder().set_tag_set(Some(tag_set)).build()?;
+
+        self.client
+            .put_object_tagging()
+            .bucket(&self.bucket)
+            .key(key)
+            .tagging(tagging)
+            .send()
+            .await
+            .with_context(|| format!("Failed to tag S3 object: {}", key))?;
+        Ok(true)
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let response = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+
+        match response {
+            Ok(output) => Ok(Some(ObjectMeta {
+                size: output.content_length().unwrap_or(0) as u64,
+                last_modified: output
+                    .last_modified()
+                    .and_then(|dt| DateTime::from_timestamp(dt.secs(), 0)),
+                etag: output.e_tag().map(|e| e.trim_matches('"').to_string()),
+            })),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to get S3 object info: {}", key)),
+        }
+    }
+
     fn get_bucket(&self) -> &str {
         &self.bucket
     }
@@ -166,10 +836,15 @@ pub struct GCSProvider {
     store: std::sync::Arc<dyn object_store::ObjectStore>,
     #[allow(dead_code)]
     bucket: String,
+    metadata: BTreeMap<String, String>,
 }
 
 impl GCSProvider {
-    pub async fn new(bucket: &str, credentials_path: Option<&str>) -> Result<Self> {
+    pub async fn new(
+        bucket: &str,
+        credentials_path: Option<&str>,
+        network: &NetworkConfig,
+    ) -> Result<Self> {
         use object_store::gcp::GoogleCloudStorageBuilder;
 
         // Set credentials path if provided
@@ -178,7 +853,9 @@ impl GCSProvider {
         }
 
         // Build GCS client
-        let builder = GoogleCloudStorageBuilder::new().with_bucket_name(bucket);
+        let builder = GoogleCloudStorageBuilder::new()
+            .with_bucket_name(bucket)
+            .with_client_options(network.client_options()?);
 
         let store = builder
             .build()
@@ -187,8 +864,15 @@ impl GCSProvider {
         Ok(Self {
             store: std::sync::Arc::new(store),
             bucket: bucket.to_string(),
+            metadata: BTreeMap::new(),
         })
     }
+
+    /// Attach `metadata` to every uploaded object.
+    pub fn with_metadata(mut self, metadata: &BTreeMap<String, String>) -> Self {
+        self.metadata = metadata.clone();
+        self
+    }
 }
 
 #[async_trait]
@@ -203,7 +887,7 @@ impl StorageProvider for GCSProvider {
 
         let path = ObjectStorePath::from(key);
         self.store
-            .put(&path, data.into())
+            .put_opts(&path, data.into(), put_options(key, &self.metadata))
             .await
             .with_context(|| format!("Failed to upload to GCS: {}", key))?;
 
@@ -211,30 +895,49 @@ impl StorageProvider for GCSProvider {
         Ok(())
     }
 
-    async fn download(&self, key: &str, output_path: &Path) -> Result<()> {
-        use object_store::path::Path as ObjectStorePath;
-        use std::fs::File;
-        use std::io::Write;
+    async fn upload_bytes(&self, key: &str, data: &[u8]) -> Result<()> {
+        info!("Uploading {} to GCS...", key);
+        self.store
+            .put_opts(
+                &object_store::path::Path::from(key),
+                data.to_vec().into(),
+                put_options(key, &self.metadata),
+            )
+            .await
+            .with_context(|| format!("Failed to upload to GCS: {}", key))?;
+        info!("Successfully uploaded: {}", key);
+        Ok(())
+    }
+
+    async fn upload_if_absent(&self, key: &str, file_path: &Path) -> Result<()> {
+        info!("Uploading {} to GCS...", key);
+        object_store_create(self.store.as_ref(), key, file_path, &self.metadata)
+            .await
+            .with_context(|| format!("Failed to upload to GCS: {}", key))?;
+        info!("Successfully uploaded: {}", key);
+        Ok(())
+    }
 
+    async fn download(&self, key: &str, output_path: &Path) -> Result<()> {
         info!("Downloading {} from GCS...", key);
-        let path = ObjectStorePath::from(key);
-        let data = self
-            .store
-            .get(&path)
+        let stream = object_store_stream(self.store.as_ref(), key)
             .await
-            .context("Failed to download from GCS")?
-            .bytes()
+            .context("Failed to download from GCS")?;
+        write_stream(stream, output_path)
             .await
             .context("Failed to read GCS object data")?;
 
-        let mut file = File::create(output_path)
-            .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
-        file.write_all(&data).context("Failed to write to file")?;
-
         info!("Downloaded to: {}", output_path.display());
         Ok(())
     }
 
+    async fn open_stream(&self, key: &str) -> Result<Option<ObjectStream>> {
+        let stream = object_store_stream(self.store.as_ref(), key)
+            .await
+            .context("Failed to download from GCS")?;
+        Ok(Some(stream))
+    }
+
     async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
         use object_store::path::Path as ObjectStorePath;
 
@@ -253,6 +956,7 @@ impl StorageProvider for GCSProvider {
                 key: meta.location.to_string(),
                 size: meta.size,
                 last_modified: Some(meta.last_modified),
+                etag: meta.e_tag,
             });
         }
 
@@ -272,10 +976,29 @@ impl StorageProvider for GCSProvider {
         Ok(())
     }
 
-    fn get_bucket(&self) -> &str {
-        &self.bucket
-    }
-}
+    async fn copy(&self, from_key: &str, to_key: &str) -> Result<bool> {
+        use object_store::path::Path as ObjectStorePath;
+
+        self.store
+            .copy(
+                &ObjectStorePath::from(from_key),
+                &ObjectStorePath::from(to_key),
+            )
+            .await
+            .with_context(|| format!("Failed to copy GCS object {} to {}", from_key, to_key))?;
+        Ok(true)
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        object_store_head(self.store.as_ref(), key)
+            .await
+            .with_context(|| format!("Failed to get GCS object info: {}", key))
+    }
+
+    fn get_bucket(&self) -> &str {
+        &self.bucket
+    }
+}
 
 // Azure Blob Storage provider using object_store crate
 // Documentation: https://docs.azure.cn/en-us/storage/common/storage-introduction
@@ -283,44 +1006,114 @@ pub struct AzureProvider {
     store: std::sync::Arc<dyn object_store::ObjectStore>,
     #[allow(dead_code)]
     container: String,
+    metadata: BTreeMap<String, String>,
+}
+
+/// How the Azure provider authenticates.
+#[derive(Debug, PartialEq)]
+pub enum AzureAuth {
+    /// Shared access signature query string (`sv=...&sig=...`)
+    Sas(String),
+    /// Service principal (app registration) with a client secret
+    ClientSecret {
+        client_id: String,
+        client_secret: String,
+        tenant_id: String,
+    },
+    /// The signed-in Azure CLI account (`az login`)
+    AzureCli,
+    /// Storage account key
+    SharedKey(String),
+    /// The VM's or container's managed identity; a client ID selects a
+    /// user-assigned identity
+    ManagedIdentity { client_id: Option<String> },
+}
+
+/// Pick the Azure auth method from the fields present in the config, preferring
+/// SAS tokens and identities over shared keys: `sas_token`, then `client_id` +
+/// `client_secret` + `tenant_id`, then `use_azure_cli`, then `account_key` (or
+/// `env_key`, from `AZURE_STORAGE_ACCOUNT_KEY`). With none of them, the managed
+/// identity is used.
+pub fn azure_auth(config: &StorageConfig, env_key: Option<String>) -> Result<AzureAuth> {
+    if let Some(sas_token) = &config.sas_token {
+        return Ok(AzureAuth::Sas(
+            sas_token.trim_start_matches('?').to_string(),
+        ));
+    }
+    if let Some(client_secret) = &config.client_secret {
+        let (Some(client_id), Some(tenant_id)) = (&config.client_id, &config.tenant_id) else {
+            return Err(anyhow::anyhow!(
+                "Azure client_secret requires client_id and tenant_id"
+            ));
+        };
+        return Ok(AzureAuth::ClientSecret {
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+            tenant_id: tenant_id.clone(),
+        });
+    }
+    if config.use_azure_cli {
+        return Ok(AzureAuth::AzureCli);
+    }
+    if let Some(key) = config.account_key.clone().or(env_key) {
+        return Ok(AzureAuth::SharedKey(key));
+    }
+    Ok(AzureAuth::ManagedIdentity {
+        client_id: config.client_id.clone(),
+    })
 }
 
 impl AzureProvider {
     pub async fn new(
         account_name: &str,
-        account_key: Option<&str>,
+        auth: AzureAuth,
         container: &str,
+        network: &NetworkConfig,
     ) -> Result<Self> {
         use object_store::azure::MicrosoftAzureBuilder;
 
         // Build Azure client
         let mut builder = MicrosoftAzureBuilder::new()
             .with_account(account_name)
-            .with_container_name(container);
-
-        // Set account key if provided, otherwise try environment variable
-        let access_key = if let Some(key) = account_key {
-            key.to_string()
-        } else if let Ok(env_key) = std::env::var("AZURE_STORAGE_ACCOUNT_KEY") {
-            env_key
-        } else {
-            return Err(anyhow::anyhow!(
-                "Azure account_key required. Set it in config (as account_key) or use AZURE_STORAGE_ACCOUNT_KEY env var. \
-                For managed identity or SAS tokens, additional implementation may be required."
-            ));
+            .with_container_name(container)
+            .with_client_options(network.client_options()?);
+
+        builder = match auth {
+            AzureAuth::Sas(token) => builder.with_sas_authorization(
+                url::form_urlencoded::parse(token.as_bytes())
+                    .into_owned()
+                    .collect::<Vec<_>>(),
+            ),
+            AzureAuth::ClientSecret {
+                client_id,
+                client_secret,
+                tenant_id,
+            } => builder.with_client_secret_authorization(client_id, client_secret, tenant_id),
+            AzureAuth::AzureCli => builder.with_use_azure_cli(true),
+            AzureAuth::SharedKey(key) => builder.with_access_key(key),
+            // object_store falls back to the instance metadata endpoint
+            AzureAuth::ManagedIdentity { client_id } => match client_id {
+                Some(client_id) => builder.with_client_id(client_id),
+                None => builder,
+            },
         };
 
-        builder = builder.with_access_key(&access_key);
-
         let store = builder.build().context(
-            "Failed to build Azure client. Ensure account_name and account_key are correct.",
+            "Failed to build Azure client. Ensure account_name and the credentials are correct.",
         )?;
 
         Ok(Self {
             store: std::sync::Arc::new(store),
             container: container.to_string(),
+            metadata: BTreeMap::new(),
         })
     }
+
+    /// Attach `metadata` to every uploaded blob.
+    pub fn with_metadata(mut self, metadata: &BTreeMap<String, String>) -> Self {
+        self.metadata = metadata.clone();
+        self
+    }
 }
 
 #[async_trait]
@@ -335,7 +1128,7 @@ impl StorageProvider for AzureProvider {
 
         let path = ObjectStorePath::from(key);
         self.store
-            .put(&path, data.into())
+            .put_opts(&path, data.into(), put_options(key, &self.metadata))
             .await
             .with_context(|| format!("Failed to upload to Azure: {}", key))?;
 
@@ -343,30 +1136,49 @@ impl StorageProvider for AzureProvider {
         Ok(())
     }
 
-    async fn download(&self, key: &str, output_path: &Path) -> Result<()> {
-        use object_store::path::Path as ObjectStorePath;
-        use std::fs::File;
-        use std::io::Write;
+    async fn upload_bytes(&self, key: &str, data: &[u8]) -> Result<()> {
+        info!("Uploading {} to Azure...", key);
+        self.store
+            .put_opts(
+                &object_store::path::Path::from(key),
+                data.to_vec().into(),
+                put_options(key, &self.metadata),
+            )
+            .await
+            .with_context(|| format!("Failed to upload to Azure: {}", key))?;
+        info!("Successfully uploaded: {}", key);
+        Ok(())
+    }
+
+    async fn upload_if_absent(&self, key: &str, file_path: &Path) -> Result<()> {
+        info!("Uploading {} to Azure...", key);
+        object_store_create(self.store.as_ref(), key, file_path, &self.metadata)
+            .await
+            .with_context(|| format!("Failed to upload to Azure: {}", key))?;
+        info!("Successfully uploaded: {}", key);
+        Ok(())
+    }
 
+    async fn download(&self, key: &str, output_path: &Path) -> Result<()> {
         info!("Downloading {} from Azure...", key);
-        let path = ObjectStorePath::from(key);
-        let data = self
-            .store
-            .get(&path)
+        let stream = object_store_stream(self.store.as_ref(), key)
             .await
-            .context("Failed to download from Azure")?
-            .bytes()
+            .context("Failed to download from Azure")?;
+        write_stream(stream, output_path)
             .await
             .context("Failed to read Azure blob data")?;
 
-        let mut file = File::create(output_path)
-            .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
-        file.write_all(&data).context("Failed to write to file")?;
-
         info!("Downloaded to: {}", output_path.display());
         Ok(())
     }
 
+    async fn open_stream(&self, key: &str) -> Result<Option<ObjectStream>> {
+        let stream = object_store_stream(self.store.as_ref(), key)
+            .await
+            .context("Failed to download from Azure")?;
+        Ok(Some(stream))
+    }
+
     async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
         use object_store::path::Path as ObjectStorePath;
 
@@ -385,6 +1197,7 @@ impl StorageProvider for AzureProvider {
                 key: meta.location.to_string(),
                 size: meta.size,
                 last_modified: Some(meta.last_modified),
+                etag: meta.e_tag,
             });
         }
 
@@ -404,6 +1217,25 @@ impl StorageProvider for AzureProvider {
         Ok(())
     }
 
+    async fn copy(&self, from_key: &str, to_key: &str) -> Result<bool> {
+        use object_store::path::Path as ObjectStorePath;
+
+        self.store
+            .copy(
+                &ObjectStorePath::from(from_key),
+                &ObjectStorePath::from(to_key),
+            )
+            .await
+            .with_context(|| format!("Failed to copy Azure blob {} to {}", from_key, to_key))?;
+        Ok(true)
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        object_store_head(self.store.as_ref(), key)
+            .await
+            .with_context(|| format!("Failed to get Azure blob info: {}", key))
+    }
+
     fn get_bucket(&self) -> &str {
         &self.container
     }
@@ -413,31 +1245,49 @@ impl StorageProvider for AzureProvider {
 pub struct B2Provider {
     account_id: String,
     application_key: String,
+    // Either can be configured alone; the other is looked up once in `new`
     bucket_id: String,
     bucket_name: String,
+    // The account the key belongs to, from the authorization
+    authorized_account_id: String,
     api_url: String,
     download_url: String,
     auth_token: Option<String>,
+    // Send each upload's SHA-1 for B2 to check, rather than `do_not_verify`
+    verify_sha1: bool,
+    // Sent as `X-Bz-Info-*` with every upload
+    metadata: BTreeMap<String, String>,
+    http: reqwest::Client,
 }
 
 impl B2Provider {
     pub async fn new(
         account_id: &str,
         application_key: &str,
-        bucket_id: &str,
+        bucket_id: Option<&str>,
         bucket_name: &str,
+        verify_sha1: bool,
+        http: reqwest::Client,
     ) -> Result<Self> {
+        if bucket_id.is_none_or(str::is_empty) && bucket_name.is_empty() {
+            return Err(ZestyError::config("B2 needs bucket (the name) or bucket_id").into());
+        }
         let mut provider = Self {
             account_id: account_id.to_string(),
             application_key: application_key.to_string(),
-            bucket_id: bucket_id.to_string(),
+            bucket_id: bucket_id.unwrap_or_default().to_string(),
             bucket_name: bucket_name.to_string(),
+            authorized_account_id: String::new(),
             api_url: String::new(),
             download_url: String::new(),
             auth_token: None,
+            verify_sha1,
+            metadata: BTreeMap::new(),
+            http,
         };
 
         provider.authenticate().await?;
+        provider.resolve_bucket().await?;
         Ok(provider)
     }
 
@@ -446,7 +1296,7 @@ impl B2Provider {
         let credentials = format!("{}:{}", self.account_id, self.application_key);
         let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let response = client
             .get("https://api.backblazeb2.com/b2api/v2/b2_authorize_account")
             .header("Authorization", format!("Basic {}", encoded))
@@ -463,6 +1313,10 @@ impl B2Provider {
             .as_str()
             .context("Missing downloadUrl in B2 response")?
             .to_string();
+        self.authorized_account_id = json["accountId"]
+            .as_str()
+            .context("Missing accountId in B2 response")?
+            .to_string();
         self.auth_token = Some(
             json["authorizationToken"]
                 .as_str()
@@ -473,8 +1327,42 @@ impl B2Provider {
         Ok(())
     }
 
+    /// Look up whichever of the bucket id and name wasn't configured with
+    /// `b2_list_buckets`. Uploads need the id and downloads the name, so with
+    /// this either one is enough.
+    async fn resolve_bucket(&mut self) -> Result<()> {
+        if !self.bucket_id.is_empty() && !self.bucket_name.is_empty() {
+            return Ok(());
+        }
+
+        // Keys restricted to one bucket may only list that bucket
+        let mut request = serde_json::json!({ "accountId": self.authorized_account_id });
+        if self.bucket_name.is_empty() {
+            request["bucketId"] = self.bucket_id.clone().into();
+        } else {
+            request["bucketName"] = self.bucket_name.clone().into();
+        }
+        let client = &self.http;
+        let response = client
+            .post(format!("{}/b2api/v2/b2_list_buckets", self.api_url))
+            .header("Authorization", self.auth_token.as_ref().unwrap())
+            .json(&request)
+            .send()
+            .await
+            .context("Failed to list B2 buckets")?;
+
+        let status = response.status();
+        let json: serde_json::Value = response.json().await?;
+        let (bucket_id, bucket_name) =
+            parse_b2_buckets(status, &json, &self.bucket_id, &self.bucket_name)?;
+        info!("Resolved B2 bucket {} ({})", bucket_name, bucket_id);
+        self.bucket_id = bucket_id;
+        self.bucket_name = bucket_name;
+        Ok(())
+    }
+
     async fn get_upload_url(&self) -> Result<(String, String)> {
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let response = client
             .post(format!("{}/b2api/v2/b2_get_upload_url", self.api_url))
             .header("Authorization", self.auth_token.as_ref().unwrap())
@@ -497,39 +1385,191 @@ impl B2Provider {
 
         Ok((upload_url, upload_auth_token))
     }
+
+    /// The `b2_upload_file` request for `data`. Its `X-Bz-Content-Sha1` is the
+    /// SHA-1 of `data` for B2 to check, or `do_not_verify` without
+    /// `verify_sha1`, which saves hashing the data up front.
+    fn upload_request(
+        &self,
+        upload_url: &str,
+        upload_auth_token: &str,
+        key: &str,
+        data: Vec<u8>,
+    ) -> reqwest::RequestBuilder {
+        use base64::Engine;
+        use sha1::{Digest, Sha1};
+
+        let content_sha1 = if self.verify_sha1 {
+            format!("{:x}", Sha1::digest(&data))
+        } else {
+            "do_not_verify".to_string()
+        };
+        let mut request = self
+            .http
+            .post(upload_url)
+            .header("Authorization", upload_auth_token)
+            .header(
+                "X-Bz-File-Name",
+                base64::engine::general_purpose::STANDARD.encode(key),
+            )
+            .header("Content-Type", content_type(key))
+            .header("X-Bz-Content-Sha1", content_sha1)
+            .header("X-Bz-Info-Author", "zesty-backup");
+        for (name, value) in &self.metadata {
+            request = request.header(format!("X-Bz-Info-{}", name), b2_info_value(value));
+        }
+        request.body(data)
+    }
+
+    /// Attach `metadata` to every uploaded file.
+    pub fn with_metadata(mut self, metadata: &BTreeMap<String, String>) -> Self {
+        self.metadata = metadata.clone();
+        self
+    }
+}
+
+impl B2Provider {
+    /// POST a large file API call, returning its JSON response.
+    async fn large_file_call(
+        &self,
+        api: &str,
+        body: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let response = self
+            .http
+            .post(format!("{}/b2api/v2/{}", self.api_url, api))
+            .header("Authorization", self.auth_token.as_ref().unwrap())
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to call {}", api))?;
+        let status = response.status();
+        if !status.is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(upload_step_failure(
+                status.as_u16(),
+                format!("B2 {} failed: {}", api, error),
+            ));
+        }
+        response
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse {} response", api))
+    }
+}
+
+#[async_trait]
+impl MultipartUpload for B2Provider {
+    async fn start_multipart(&self, key: &str) -> Result<String> {
+        let mut file_info = serde_json::Map::new();
+        file_info.insert("author".to_string(), "zesty-backup".into());
+        for (name, value) in &self.metadata {
+            file_info.insert(name.clone(), value.clone().into());
+        }
+        let json = self
+            .large_file_call(
+                "b2_start_large_file",
+                serde_json::json!({
+                    "bucketId": self.bucket_id,
+                    "fileName": key,
+                    "contentType": content_type(key),
+                    "fileInfo": file_info,
+                }),
+            )
+            .await?;
+        json["fileId"]
+            .as_str()
+            .map(str::to_string)
+            .context("Missing fileId")
+    }
+
+    async fn upload_part(
+        &self,
+        _key: &str,
+        upload_id: &str,
+        number: u32,
+        data: Vec<u8>,
+    ) -> Result<String> {
+        use sha1::{Digest, Sha1};
+
+        let json = self
+            .large_file_call(
+                "b2_get_upload_part_url",
+                serde_json::json!({ "fileId": upload_id }),
+            )
+            .await?;
+        let upload_url = json["uploadUrl"].as_str().context("Missing uploadUrl")?;
+        let upload_auth_token = json["authorizationToken"]
+            .as_str()
+            .context("Missing authorizationToken")?;
+        // Finishing the upload lists every part's SHA-1
+        let sha1 = format!("{:x}", Sha1::digest(&data));
+        let response = self
+            .http
+            .post(upload_url)
+            .header("Authorization", upload_auth_token)
+            .header("X-Bz-Part-Number", number)
+            .header("X-Bz-Content-Sha1", &sha1)
+            .body(data)
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload part {} to B2", number))?;
+        let status = response.status();
+        if !status.is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(upload_step_failure(
+                status.as_u16(),
+                format!("B2 part upload failed: {}", error),
+            ));
+        }
+        Ok(sha1)
+    }
+
+    async fn complete_multipart(
+        &self,
+        _key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> Result<()> {
+        let sha1s: Vec<&str> = parts.iter().map(|part| part.etag.as_str()).collect();
+        self.large_file_call(
+            "b2_finish_large_file",
+            serde_json::json!({ "fileId": upload_id, "partSha1Array": sha1s }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn abort_multipart(&self, _key: &str, upload_id: &str) -> Result<()> {
+        self.large_file_call(
+            "b2_cancel_large_file",
+            serde_json::json!({ "fileId": upload_id }),
+        )
+        .await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl StorageProvider for B2Provider {
     async fn upload(&self, key: &str, file_path: &Path) -> Result<()> {
-        use base64::Engine;
-        use sha1::{Digest, Sha1};
         use std::fs;
 
+        if wants_multipart(file_path) {
+            info!("Uploading {} to B2 as a large file...", key);
+            let policy = retry::RetryPolicy::default();
+            resumable_upload(self, key, file_path, MULTIPART_PART_SIZE, &policy).await?;
+            info!("Successfully uploaded: {}", key);
+            return Ok(());
+        }
+
         info!("Uploading {} to B2...", key);
         let data = fs::read(file_path)
             .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
 
         let (upload_url, upload_auth_token) = self.get_upload_url().await?;
-
-        // Calculate SHA1
-        let mut hasher = Sha1::new();
-        hasher.update(&data);
-        let sha1 = hasher.finalize();
-        let sha1_hex = format!("{:x}", sha1);
-
-        let client = reqwest::Client::new();
-        let response = client
-            .post(&upload_url)
-            .header("Authorization", upload_auth_token)
-            .header(
-                "X-Bz-File-Name",
-                base64::engine::general_purpose::STANDARD.encode(key),
-            )
-            .header("Content-Type", "b2/x-auto")
-            .header("X-Bz-Content-Sha1", sha1_hex)
-            .header("X-Bz-Info-Author", "zesty-backup")
-            .body(data)
+        let response = self
+            .upload_request(&upload_url, &upload_auth_token, key, data)
             .send()
             .await
             .context("Failed to upload to B2")?;
@@ -550,7 +1590,7 @@ impl StorageProvider for B2Provider {
         info!("Downloading {} from B2...", key);
         let url = format!("{}/file/{}/{}", self.download_url, self.bucket_name, key);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let response = client
             .get(&url)
             .header("Authorization", self.auth_token.as_ref().unwrap())
@@ -572,7 +1612,7 @@ impl StorageProvider for B2Provider {
     }
 
     async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let mut items = Vec::new();
         let mut start_file_name: Option<String> = None;
 
@@ -621,6 +1661,7 @@ impl StorageProvider for B2Provider {
                     key: file_name,
                     size,
                     last_modified: DateTime::from_timestamp(timestamp_ms as i64, 0),
+                    etag: None,
                 });
             }
 
@@ -635,7 +1676,7 @@ impl StorageProvider for B2Provider {
 
     async fn delete(&self, key: &str) -> Result<()> {
         // First get file info
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let response = client
             .post(format!("{}/b2api/v2/b2_list_file_versions", self.api_url))
             .header("Authorization", self.auth_token.as_ref().unwrap())
@@ -677,6 +1718,21 @@ impl StorageProvider for B2Provider {
         Ok(())
     }
 
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        // b2_get_file_info needs a file ID; a HEAD of the download URL looks
+        // the file up by name and returns its info as headers
+        let url = format!("{}/file/{}/{}", self.download_url, self.bucket_name, key);
+        let response = self
+            .http
+            .head(&url)
+            .header("Authorization", self.auth_token.as_ref().unwrap())
+            .send()
+            .await
+            .context("Failed to get file info from B2")?;
+
+        parse_b2_head(response.status(), response.headers())
+    }
+
     fn get_bucket(&self) -> &str {
         &self.bucket_name
     }
@@ -686,16 +1742,33 @@ impl StorageProvider for B2Provider {
 pub struct GoogleDriveProvider {
     access_token: String,
     folder_id: Option<String>,
+    // Set as each uploaded file's `properties`
+    metadata: BTreeMap<String, String>,
+    breaker: CircuitBreaker,
+    http: reqwest::Client,
 }
 
 impl GoogleDriveProvider {
-    pub async fn new(access_token: &str, folder_id: Option<&str>) -> Result<Self> {
+    pub async fn new(
+        access_token: &str,
+        folder_id: Option<&str>,
+        http: reqwest::Client,
+    ) -> Result<Self> {
         Ok(Self {
             access_token: access_token.to_string(),
             folder_id: folder_id.map(|s| s.to_string()),
+            metadata: BTreeMap::new(),
+            breaker: CircuitBreaker::new("Google Drive"),
+            http,
         })
     }
 
+    /// Attach `metadata` to every uploaded file as custom properties.
+    pub fn with_metadata(mut self, metadata: &BTreeMap<String, String>) -> Self {
+        self.metadata = metadata.clone();
+        self
+    }
+
     async fn get_folder_id(&self) -> Result<String> {
         if let Some(ref folder_id) = self.folder_id {
             return Ok(folder_id.clone());
@@ -723,25 +1796,28 @@ impl StorageProvider for GoogleDriveProvider {
         // Create file metadata
         let metadata = serde_json::json!({
             "name": file_name,
-            "parents": [folder_id]
+            "parents": [folder_id],
+            "mimeType": content_type(key),
+            "properties": self.metadata,
         });
 
         // Upload file using multipart upload
-        let client = reqwest::Client::new();
-        let form = reqwest::multipart::Form::new()
-            .text("metadata", serde_json::to_string(&metadata)?)
-            .part(
-                "file",
-                reqwest::multipart::Part::bytes(data).file_name(file_name.to_string()),
-            );
+        let client = &self.http;
+        let metadata = serde_json::to_string(&metadata)?;
+        let form = || {
+            reqwest::multipart::Form::new()
+                .text("metadata", metadata.clone())
+                .part("file", file_part(data.clone(), file_name, key))
+        };
 
-        let response = client
-            .post("https://www.googleapis.com/upload/drive/v3/files?uploadType=multipart")
-            .bearer_auth(&self.access_token)
-            .multipart(form)
-            .send()
-            .await
-            .context("Failed to upload to Google Drive")?;
+        let response = retry::send(&self.breaker, || {
+            client
+                .post("https://www.googleapis.com/upload/drive/v3/files?uploadType=multipart")
+                .bearer_auth(&self.access_token)
+                .multipart(form())
+        })
+        .await
+        .context("Failed to upload to Google Drive")?;
 
         if !response.status().is_success() {
             let error = response.text().await.unwrap_or_default();
@@ -765,7 +1841,7 @@ impl StorageProvider for GoogleDriveProvider {
             .and_then(|n| n.to_str())
             .unwrap_or(key);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let query = format!(
             "name='{}' and '{}' in parents and trashed=false",
             file_name.replace("'", "\\'"),
@@ -776,12 +1852,11 @@ impl StorageProvider for GoogleDriveProvider {
             url::form_urlencoded::byte_serialize(query.as_bytes()).collect::<String>()
         );
 
-        let response = client
-            .get(&url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to search Google Drive")?;
+        let response = retry::send(&self.breaker, || {
+            client.get(&url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to search Google Drive")?;
 
         let files: serde_json::Value = response.json().await?;
         let file_id = files["files"]
@@ -795,12 +1870,11 @@ impl StorageProvider for GoogleDriveProvider {
             "https://www.googleapis.com/drive/v3/files/{}?alt=media",
             file_id
         );
-        let file_response = client
-            .get(&download_url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to download from Google Drive")?;
+        let file_response = retry::send(&self.breaker, || {
+            client.get(&download_url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to download from Google Drive")?;
 
         let data = file_response.bytes().await?;
         let mut file = File::create(output_path)
@@ -813,42 +1887,20 @@ impl StorageProvider for GoogleDriveProvider {
 
     async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
         let folder_id = self.get_folder_id().await?;
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let query = format!("'{}' in parents and trashed=false", folder_id);
         let url = format!("https://www.googleapis.com/drive/v3/files?q={}&fields=files(id,name,size,modifiedTime)", 
             url::form_urlencoded::byte_serialize(query.as_bytes()).collect::<String>());
 
-        let response = client
-            .get(&url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to list Google Drive files")?;
+        let response = retry::send(&self.breaker, || {
+            client.get(&url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to list Google Drive files")?;
 
+        let status = response.status();
         let files: serde_json::Value = response.json().await?;
-        let mut items = Vec::new();
-
-        if let Some(files_array) = files["files"].as_array() {
-            for file in files_array {
-                if let Some(name) = file["name"].as_str() {
-                    if name.starts_with(prefix) {
-                        items.push(BackupItem {
-                            key: name.to_string(),
-                            size: file["size"]
-                                .as_str()
-                                .and_then(|s| s.parse::<u64>().ok())
-                                .unwrap_or(0),
-                            last_modified: file["modifiedTime"]
-                                .as_str()
-                                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
-                                .map(|dt| dt.with_timezone(&Utc)),
-                        });
-                    }
-                }
-            }
-        }
-
-        Ok(items)
+        parse_drive_list(status, &files, prefix)
     }
 
     async fn delete(&self, key: &str) -> Result<()> {
@@ -859,7 +1911,7 @@ impl StorageProvider for GoogleDriveProvider {
             .and_then(|n| n.to_str())
             .unwrap_or(key);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let query = format!(
             "name='{}' and '{}' in parents and trashed=false",
             file_name.replace("'", "\\'"),
@@ -870,12 +1922,11 @@ impl StorageProvider for GoogleDriveProvider {
             url::form_urlencoded::byte_serialize(query.as_bytes()).collect::<String>()
         );
 
-        let response = client
-            .get(&url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to search Google Drive")?;
+        let response = retry::send(&self.breaker, || {
+            client.get(&url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to search Google Drive")?;
 
         let files: serde_json::Value = response.json().await?;
         let file_id = files["files"]
@@ -884,20 +1935,68 @@ impl StorageProvider for GoogleDriveProvider {
             .and_then(|f| f["id"].as_str())
             .context("File not found in Google Drive")?;
 
-        client
-            .delete(format!(
-                "https://www.googleapis.com/drive/v3/files/{}",
-                file_id
-            ))
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to delete from Google Drive")?;
+        retry::send(&self.breaker, || {
+            client
+                .delete(format!(
+                    "https://www.googleapis.com/drive/v3/files/{}",
+                    file_id
+                ))
+                .bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to delete from Google Drive")?;
 
         info!("Deleted from Google Drive: {}", key);
         Ok(())
     }
 
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let folder_id = self.get_folder_id().await?;
+        let file_name = Path::new(key)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(key);
+
+        let client = &self.http;
+        let query = format!(
+            "name='{}' and '{}' in parents and trashed=false",
+            file_name.replace("'", "\\'"),
+            folder_id
+        );
+        let url = format!(
+            "https://www.googleapis.com/drive/v3/files?q={}&fields=files(id,name,size,modifiedTime)",
+            url::form_urlencoded::byte_serialize(query.as_bytes()).collect::<String>()
+        );
+
+        let response = retry::send(&self.breaker, || {
+            client.get(&url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to search Google Drive")?;
+
+        let status = response.status();
+        let files: serde_json::Value = response.json().await?;
+        Ok(parse_drive_list(status, &files, "")?
+            .into_iter()
+            .find(|item| item.key == file_name)
+            .map(ObjectMeta::from))
+    }
+
+    async fn usage(&self) -> Result<StorageUsage> {
+        let client = &self.http;
+        let response = retry::send(&self.breaker, || {
+            client
+                .get("https://www.googleapis.com/drive/v3/about")
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .query(&[("fields", "storageQuota")])
+        })
+        .await
+        .context("Failed to get Google Drive storage quota")?;
+
+        let json: serde_json::Value = response.json().await?;
+        parse_drive_usage(&json)
+    }
+
     fn get_bucket(&self) -> &str {
         "Google Drive"
     }
@@ -907,18 +2006,31 @@ impl StorageProvider for GoogleDriveProvider {
 pub struct OneDriveProvider {
     access_token: String,
     folder_path: Option<String>,
+    http: reqwest::Client,
 }
 
 impl OneDriveProvider {
-    pub async fn new(access_token: &str, folder_path: Option<&str>) -> Result<Self> {
+    pub async fn new(
+        access_token: &str,
+        folder_path: Option<&str>,
+        http: reqwest::Client,
+    ) -> Result<Self> {
         Ok(Self {
             access_token: access_token.to_string(),
             folder_path: folder_path.map(|s| s.to_string()),
+            http,
         })
     }
 
     async fn get_folder_id(&self) -> Result<String> {
-        let client = reqwest::Client::new();
+        self.find_folder_id()
+            .await?
+            .context("OneDrive folder not found")
+    }
+
+    /// The folder's ID, or `None` if it hasn't been created yet.
+    async fn find_folder_id(&self) -> Result<Option<String>> {
+        let client = &self.http;
         let path = self.folder_path.as_deref().unwrap_or("/drive/root:");
 
         let url = format!("https://graph.microsoft.com/v1.0/me{}", path);
@@ -929,10 +2041,13 @@ impl OneDriveProvider {
             .await
             .context("Failed to get OneDrive folder")?;
 
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
         let folder: serde_json::Value = response.json().await?;
         folder["id"]
             .as_str()
-            .map(|s| s.to_string())
+            .map(|s| Some(s.to_string()))
             .context("Failed to get folder ID")
     }
 }
@@ -956,10 +2071,11 @@ impl StorageProvider for OneDriveProvider {
             "https://graph.microsoft.com/v1.0/me/drive/items/{}/children/{}:/content",
             folder_id, file_name
         );
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let response = client
             .put(&url)
             .bearer_auth(&self.access_token)
+            .header("Content-Type", content_type(key))
             .body(data)
             .send()
             .await
@@ -985,7 +2101,7 @@ impl StorageProvider for OneDriveProvider {
             .and_then(|n| n.to_str())
             .unwrap_or(key);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let url = format!(
             "https://graph.microsoft.com/v1.0/me/drive/items/{}/children",
             folder_id
@@ -1024,8 +2140,10 @@ impl StorageProvider for OneDriveProvider {
     }
 
     async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
-        let folder_id = self.get_folder_id().await?;
-        let client = reqwest::Client::new();
+        let Some(folder_id) = self.find_folder_id().await? else {
+            return Ok(Vec::new());
+        };
+        let client = &self.http;
         let url = format!(
             "https://graph.microsoft.com/v1.0/me/drive/items/{}/children",
             folder_id
@@ -1037,27 +2155,9 @@ impl StorageProvider for OneDriveProvider {
             .await
             .context("Failed to list OneDrive files")?;
 
+        let status = response.status();
         let files: serde_json::Value = response.json().await?;
-        let mut items = Vec::new();
-
-        if let Some(files_array) = files["value"].as_array() {
-            for file in files_array {
-                if let Some(name) = file["name"].as_str() {
-                    if name.starts_with(prefix) {
-                        items.push(BackupItem {
-                            key: name.to_string(),
-                            size: file["size"].as_u64().unwrap_or(0),
-                            last_modified: file["lastModifiedDateTime"]
-                                .as_str()
-                                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
-                                .map(|dt| dt.with_timezone(&Utc)),
-                        });
-                    }
-                }
-            }
-        }
-
-        Ok(items)
+        parse_onedrive_list(status, &files, prefix)
     }
 
     async fn delete(&self, key: &str) -> Result<()> {
@@ -1067,7 +2167,7 @@ impl StorageProvider for OneDriveProvider {
             .and_then(|n| n.to_str())
             .unwrap_or(key);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let url = format!(
             "https://graph.microsoft.com/v1.0/me/drive/items/{}/children",
             folder_id
@@ -1100,6 +2200,33 @@ impl StorageProvider for OneDriveProvider {
         Ok(())
     }
 
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let Some(folder_id) = self.find_folder_id().await? else {
+            return Ok(None);
+        };
+        let file_name = Path::new(key)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(key);
+
+        // Addressed by path relative to the folder
+        let url = format!(
+            "https://graph.microsoft.com/v1.0/me/drive/items/{}:/{}",
+            folder_id, file_name
+        );
+        let response = self
+            .http
+            .get(&url)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await
+            .context("Failed to get OneDrive file")?;
+
+        let status = response.status();
+        let json: serde_json::Value = response.json().await?;
+        parse_onedrive_item(status, &json)
+    }
+
     fn get_bucket(&self) -> &str {
         "OneDrive"
     }
@@ -1109,13 +2236,21 @@ impl StorageProvider for OneDriveProvider {
 pub struct DropboxProvider {
     access_token: String,
     folder_path: Option<String>,
+    breaker: CircuitBreaker,
+    http: reqwest::Client,
 }
 
 impl DropboxProvider {
-    pub async fn new(access_token: &str, folder_path: Option<&str>) -> Result<Self> {
+    pub async fn new(
+        access_token: &str,
+        folder_path: Option<&str>,
+        http: reqwest::Client,
+    ) -> Result<Self> {
         Ok(Self {
             access_token: access_token.to_string(),
             folder_path: folder_path.map(|s| s.to_string()),
+            breaker: CircuitBreaker::new("Dropbox"),
+            http,
         })
     }
 
@@ -1139,23 +2274,26 @@ impl StorageProvider for DropboxProvider {
             .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
 
         let path = self.get_path(key);
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://content.dropboxapi.com/2/files/upload")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .header(
-                "Dropbox-API-Arg",
-                serde_json::json!({
-                    "path": path,
-                    "mode": "overwrite"
-                })
-                .to_string(),
-            )
-            .header("Content-Type", "application/octet-stream")
-            .body(data)
-            .send()
-            .await
-            .context("Failed to upload to Dropbox")?;
+        let client = &self.http;
+        let response = retry::send(&self.breaker, || {
+            client
+                .post("https://content.dropboxapi.com/2/files/upload")
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .header(
+                    "Dropbox-API-Arg",
+                    serde_json::json!({
+                        "path": path,
+                        "mode": "overwrite"
+                    })
+                    .to_string(),
+                )
+                // The content endpoint only accepts octet-stream; Dropbox
+                // derives the type it serves from the file name
+                .header("Content-Type", "application/octet-stream")
+                .body(data.clone())
+        })
+        .await
+        .context("Failed to upload to Dropbox")?;
 
         if !response.status().is_success() {
             let error = response.text().await.unwrap_or_default();
@@ -1172,17 +2310,18 @@ impl StorageProvider for DropboxProvider {
 
         info!("Downloading {} from Dropbox...", key);
         let path = self.get_path(key);
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://content.dropboxapi.com/2/files/download")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .header(
-                "Dropbox-API-Arg",
-                serde_json::json!({ "path": path }).to_string(),
-            )
-            .send()
-            .await
-            .context("Failed to download from Dropbox")?;
+        let client = &self.http;
+        let response = retry::send(&self.breaker, || {
+            client
+                .post("https://content.dropboxapi.com/2/files/download")
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .header(
+                    "Dropbox-API-Arg",
+                    serde_json::json!({ "path": path }).to_string(),
+                )
+        })
+        .await
+        .context("Failed to download from Dropbox")?;
 
         if !response.status().is_success() {
             let error = response.text().await.unwrap_or_default();
@@ -1199,51 +2338,35 @@ impl StorageProvider for DropboxProvider {
 
     async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
         let base_path = self.folder_path.as_deref().unwrap_or("");
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://api.dropboxapi.com/2/files/list_folder")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .json(&serde_json::json!({
-                "path": base_path,
-                "recursive": false
-            }))
-            .send()
-            .await
-            .context("Failed to list Dropbox files")?;
+        let client = &self.http;
+        let response = retry::send(&self.breaker, || {
+            client
+                .post("https://api.dropboxapi.com/2/files/list_folder")
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .json(&serde_json::json!({
+                    "path": base_path,
+                    "recursive": false
+                }))
+        })
+        .await
+        .context("Failed to list Dropbox files")?;
 
+        let status = response.status();
         let files: serde_json::Value = response.json().await?;
-        let mut items = Vec::new();
-
-        if let Some(entries) = files["entries"].as_array() {
-            for entry in entries {
-                if let Some(name) = entry["name"].as_str() {
-                    if name.starts_with(prefix) && entry[".tag"].as_str() == Some("file") {
-                        items.push(BackupItem {
-                            key: name.to_string(),
-                            size: entry["size"].as_u64().unwrap_or(0),
-                            last_modified: entry["client_modified"]
-                                .as_str()
-                                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
-                                .map(|dt| dt.with_timezone(&Utc)),
-                        });
-                    }
-                }
-            }
-        }
-
-        Ok(items)
+        parse_dropbox_list(status, &files, prefix)
     }
 
     async fn delete(&self, key: &str) -> Result<()> {
         let path = self.get_path(key);
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://api.dropboxapi.com/2/files/delete_v2")
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .json(&serde_json::json!({ "path": path }))
-            .send()
-            .await
-            .context("Failed to delete from Dropbox")?;
+        let client = &self.http;
+        let response = retry::send(&self.breaker, || {
+            client
+                .post("https://api.dropboxapi.com/2/files/delete_v2")
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .json(&serde_json::json!({ "path": path }))
+        })
+        .await
+        .context("Failed to delete from Dropbox")?;
 
         if !response.status().is_success() {
             let error = response.text().await.unwrap_or_default();
@@ -1254,6 +2377,61 @@ impl StorageProvider for DropboxProvider {
         Ok(())
     }
 
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let path = self.get_path(key);
+        let client = &self.http;
+        let response = retry::send(&self.breaker, || {
+            client
+                .post("https://api.dropboxapi.com/2/files/get_metadata")
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .json(&serde_json::json!({ "path": path }))
+        })
+        .await
+        .context("Failed to get Dropbox file metadata")?;
+
+        // Missing paths come back as a 409 with a path/not_found error
+        let status = response.status();
+        let json: serde_json::Value = response.json().await?;
+        if status == reqwest::StatusCode::CONFLICT
+            && json["error_summary"]
+                .as_str()
+                .is_some_and(|e| e.starts_with("path/not_found"))
+        {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("Dropbox get_metadata failed: {}", json));
+        }
+
+        Ok(Some(ObjectMeta {
+            size: json["size"].as_u64().unwrap_or(0),
+            last_modified: json["server_modified"]
+                .as_str()
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .map(|dt| dt.with_timezone(&Utc)),
+            etag: json["content_hash"].as_str().map(|s| s.to_string()),
+        }))
+    }
+
+    async fn usage(&self) -> Result<StorageUsage> {
+        let client = &self.http;
+        let response = retry::send(&self.breaker, || {
+            client
+                .post("https://api.dropboxapi.com/2/users/get_space_usage")
+                .header("Authorization", format!("Bearer {}", self.access_token))
+        })
+        .await
+        .context("Failed to get Dropbox space usage")?;
+
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("Dropbox space usage failed: {}", error));
+        }
+
+        let json: serde_json::Value = response.json().await?;
+        parse_dropbox_usage(&json)
+    }
+
     fn get_bucket(&self) -> &str {
         "Dropbox"
     }
@@ -1263,13 +2441,21 @@ impl StorageProvider for DropboxProvider {
 pub struct BoxProvider {
     access_token: String,
     folder_id: Option<String>,
+    breaker: CircuitBreaker,
+    http: reqwest::Client,
 }
 
 impl BoxProvider {
-    pub async fn new(access_token: &str, folder_id: Option<&str>) -> Result<Self> {
+    pub async fn new(
+        access_token: &str,
+        folder_id: Option<&str>,
+        http: reqwest::Client,
+    ) -> Result<Self> {
         Ok(Self {
             access_token: access_token.to_string(),
             folder_id: folder_id.map(|s| s.to_string()),
+            breaker: CircuitBreaker::new("Box"),
+            http,
         })
     }
 
@@ -1298,26 +2484,26 @@ impl StorageProvider for BoxProvider {
             .unwrap_or(key);
 
         // Box uses multipart upload
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let attributes = serde_json::json!({
             "name": file_name,
             "parent": { "id": folder_id }
         });
 
-        let form = reqwest::multipart::Form::new()
-            .text("attributes", attributes.to_string())
-            .part(
-                "file",
-                reqwest::multipart::Part::bytes(data).file_name(file_name.to_string()),
-            );
+        let form = || {
+            reqwest::multipart::Form::new()
+                .text("attributes", attributes.to_string())
+                .part("file", file_part(data.clone(), file_name, key))
+        };
 
-        let response = client
-            .post("https://upload.box.com/api/2.0/files/content")
-            .bearer_auth(&self.access_token)
-            .multipart(form)
-            .send()
-            .await
-            .context("Failed to upload to Box")?;
+        let response = retry::send(&self.breaker, || {
+            client
+                .post("https://upload.box.com/api/2.0/files/content")
+                .bearer_auth(&self.access_token)
+                .multipart(form())
+        })
+        .await
+        .context("Failed to upload to Box")?;
 
         if !response.status().is_success() {
             let error = response.text().await.unwrap_or_default();
@@ -1339,15 +2525,14 @@ impl StorageProvider for BoxProvider {
             .and_then(|n| n.to_str())
             .unwrap_or(key);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         // First, find the file
         let url = format!("https://api.box.com/2.0/folders/{}/items", folder_id);
-        let response = client
-            .get(&url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to list Box files")?;
+        let response = retry::send(&self.breaker, || {
+            client.get(&url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to list Box files")?;
 
         let files: serde_json::Value = response.json().await?;
         let file_id = files["entries"]
@@ -1358,12 +2543,11 @@ impl StorageProvider for BoxProvider {
 
         // Download the file
         let download_url = format!("https://api.box.com/2.0/files/{}/content", file_id);
-        let file_response = client
-            .get(&download_url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to download from Box")?;
+        let file_response = retry::send(&self.breaker, || {
+            client.get(&download_url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to download from Box")?;
 
         let data = file_response.bytes().await?;
         let mut file = File::create(output_path)?;
@@ -1375,36 +2559,17 @@ impl StorageProvider for BoxProvider {
 
     async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
         let folder_id = self.get_folder_id().await?;
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let url = format!("https://api.box.com/2.0/folders/{}/items", folder_id);
-        let response = client
-            .get(&url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to list Box files")?;
+        let response = retry::send(&self.breaker, || {
+            client.get(&url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to list Box files")?;
 
+        let status = response.status();
         let files: serde_json::Value = response.json().await?;
-        let mut items = Vec::new();
-
-        if let Some(entries) = files["entries"].as_array() {
-            for entry in entries {
-                if let Some(name) = entry["name"].as_str() {
-                    if name.starts_with(prefix) && entry["type"].as_str() == Some("file") {
-                        items.push(BackupItem {
-                            key: name.to_string(),
-                            size: entry["size"].as_u64().unwrap_or(0),
-                            last_modified: entry["modified_at"]
-                                .as_str()
-                                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
-                                .map(|dt| dt.with_timezone(&Utc)),
-                        });
-                    }
-                }
-            }
-        }
-
-        Ok(items)
+        parse_box_list(status, &files, prefix)
     }
 
     async fn delete(&self, key: &str) -> Result<()> {
@@ -1414,14 +2579,13 @@ impl StorageProvider for BoxProvider {
             .and_then(|n| n.to_str())
             .unwrap_or(key);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let url = format!("https://api.box.com/2.0/folders/{}/items", folder_id);
-        let response = client
-            .get(&url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to list Box files")?;
+        let response = retry::send(&self.breaker, || {
+            client.get(&url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to list Box files")?;
 
         let files: serde_json::Value = response.json().await?;
         let file_id = files["entries"]
@@ -1430,17 +2594,65 @@ impl StorageProvider for BoxProvider {
             .and_then(|f| f["id"].as_str())
             .context("File not found in Box")?;
 
-        client
-            .delete(format!("https://api.box.com/2.0/files/{}", file_id))
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-            .context("Failed to delete from Box")?;
+        retry::send(&self.breaker, || {
+            client
+                .delete(format!("https://api.box.com/2.0/files/{}", file_id))
+                .bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to delete from Box")?;
 
         info!("Deleted from Box: {}", key);
         Ok(())
     }
 
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let folder_id = self.get_folder_id().await?;
+        let file_name = Path::new(key)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(key);
+
+        // The upload preflight check looks the name up in the folder and
+        // reports an existing file as a conflict, with its ID
+        let client = &self.http;
+        let attributes = serde_json::json!({
+            "name": file_name,
+            "parent": { "id": folder_id }
+        });
+        let response = retry::send(&self.breaker, || {
+            client
+                .request(
+                    reqwest::Method::OPTIONS,
+                    "https://api.box.com/2.0/files/content",
+                )
+                .bearer_auth(&self.access_token)
+                .json(&attributes)
+        })
+        .await
+        .context("Failed to look up Box file")?;
+
+        let status = response.status();
+        let json: serde_json::Value = response.json().await.unwrap_or_default();
+        let Some(file_id) = parse_box_conflict(status, &json)? else {
+            return Ok(None);
+        };
+
+        let url = format!(
+            "https://api.box.com/2.0/files/{}?fields=size,modified_at,sha1",
+            file_id
+        );
+        let response = retry::send(&self.breaker, || {
+            client.get(&url).bearer_auth(&self.access_token)
+        })
+        .await
+        .context("Failed to get Box file info")?;
+
+        let status = response.status();
+        let json: serde_json::Value = response.json().await?;
+        parse_box_file(status, &json)
+    }
+
     fn get_bucket(&self) -> &str {
         "Box"
     }
@@ -1454,6 +2666,7 @@ pub struct MegaProvider {
     password: String,
     folder_path: Option<String>,
     mega_cmd_path: Option<String>,
+    session_file: Option<PathBuf>, // Cached MEGAcmd session, reused across runs
 }
 
 impl MegaProvider {
@@ -1475,6 +2688,7 @@ impl MegaProvider {
             password: password.to_string(),
             folder_path: folder_path.map(|s| s.to_string()),
             mega_cmd_path,
+            session_file: session_cache_path("mega-session"),
         })
     }
 
@@ -1505,6 +2719,24 @@ impl MegaProvider {
             return Ok(());
         }
 
+        // Resume the session cached by an earlier run, which is much faster
+        // than a full login and doesn't count against MEGA's login limits
+        if let Some(session) = self.cached_session() {
+            let resume_cmd = Command::new(self.get_mega_cmd())
+                .arg("login")
+                .arg(&session)
+                .output()
+                .context("Failed to login to MEGA")?;
+            if resume_cmd.status.success() {
+                info!("Resumed cached MEGA session");
+                return Ok(());
+            }
+            warn!("Cached MEGA session is no longer valid; logging in again");
+            if let Some(ref path) = self.session_file {
+                std::fs::remove_file(path).ok();
+            }
+        }
+
         // Need to login - MEGAcmd requires interactive login or session file
         // We'll use the login command with credentials
         info!("Logging into MEGA...");
@@ -1521,8 +2753,81 @@ impl MegaProvider {
         }
 
         info!("Successfully logged into MEGA");
+        self.cache_session();
         Ok(())
     }
+
+    /// The cached session for this account, if there is one.
+    fn cached_session(&self) -> Option<String> {
+        let contents = std::fs::read_to_string(self.session_file.as_ref()?).ok()?;
+        let (email, session) = contents.trim().split_once('\n')?;
+        (email == self.email && !session.is_empty()).then(|| session.to_string())
+    }
+
+    /// Save the current session so the next run can resume it. Failing to is
+    /// only a warning: the next run logs in again.
+    fn cache_session(&self) {
+        use std::process::Command;
+
+        let Some(ref path) = self.session_file else {
+            return;
+        };
+        let session = Command::new(self.get_mega_cmd()).arg("session").output();
+        // `session` prints "Your (secret) session is: <session>"
+        let session = match session {
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .last()
+                .map(str::to_string),
+            _ => None,
+        };
+        let Some(session) = session else {
+            warn!("Could not read the MEGA session to cache it");
+            return;
+        };
+        if let Err(e) = write_private_file(path, &format!("{}\n{}\n", self.email, session)) {
+            warn!("Failed to cache MEGA session: {:#}", e);
+        }
+    }
+}
+
+/// Where a provider session is cached between runs: `zesty-backup/<name>` in
+/// the user's config directory (`$XDG_CONFIG_HOME`, or `~/.config`).
+fn session_cache_path(name: &str) -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("zesty-backup").join(name))
+}
+
+/// Write a file readable only by the current user (mode 0600 on unix), since
+/// it holds credentials.
+fn write_private_file(path: &Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    // The mode only applies to new files; tighten one left by an older run
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents.as_bytes())?;
+    Ok(())
 }
 
 #[async_trait]
@@ -1655,38 +2960,12 @@ impl StorageProvider for MegaProvider {
             .output()
             .context("Failed to execute MEGAcmd list")?;
 
-        if !list_cmd.status.success() {
-            let error = String::from_utf8_lossy(&list_cmd.stderr);
-            return Err(anyhow::anyhow!("MEGA list failed: {}", error));
-        }
-
-        let output = String::from_utf8_lossy(&list_cmd.stdout);
-        let mut items = Vec::new();
-
-        // Parse MEGAcmd ls output (format: permissions size date time name)
-        for line in output.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 5 {
-                // Check if it's a file (not a directory)
-                let perms = parts[0];
-                if perms.starts_with('-') {
-                    let size_str = parts[4];
-                    let name = parts[5..].join(" ");
-
-                    if name.starts_with(prefix) {
-                        if let Ok(size) = size_str.parse::<u64>() {
-                            items.push(BackupItem {
-                                key: name,
-                                size,
-                                last_modified: None, // MEGAcmd ls doesn't provide timestamps in simple format
-                            });
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(items)
+        parse_mega_ls(
+            list_cmd.status.success(),
+            &String::from_utf8_lossy(&list_cmd.stdout),
+            &String::from_utf8_lossy(&list_cmd.stderr),
+            prefix,
+        )
     }
 
     async fn delete(&self, key: &str) -> Result<()> {
@@ -1715,6 +2994,29 @@ impl StorageProvider for MegaProvider {
         Ok(())
     }
 
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        use std::process::Command;
+
+        self.ensure_logged_in().await?;
+
+        // `mega-ls -l` of a file lists just that file
+        let list_cmd = Command::new(self.get_mega_cmd())
+            .arg("ls")
+            .arg("-l")
+            .arg(self.get_remote_path(key))
+            .output()
+            .context("Failed to execute MEGAcmd list")?;
+
+        Ok(parse_mega_ls(
+            list_cmd.status.success(),
+            &String::from_utf8_lossy(&list_cmd.stdout),
+            &String::from_utf8_lossy(&list_cmd.stderr),
+            "",
+        )?
+        .pop()
+        .map(ObjectMeta::from))
+    }
+
     fn get_bucket(&self) -> &str {
         "MEGA"
     }
@@ -1726,6 +3028,7 @@ pub struct PCloudProvider {
     access_token: String,
     api_host: String, // api.pcloud.com (US) or eapi.pcloud.com (EU)
     folder_path: Option<String>,
+    http: reqwest::Client,
 }
 
 impl PCloudProvider {
@@ -1733,6 +3036,7 @@ impl PCloudProvider {
         access_token: &str,
         region: Option<&str>, // "us" or "eu", defaults to "us"
         folder_path: Option<&str>,
+        http: reqwest::Client,
     ) -> Result<Self> {
         let api_host = match region {
             Some("eu") | Some("europe") => "https://eapi.pcloud.com",
@@ -1743,6 +3047,7 @@ impl PCloudProvider {
             access_token: access_token.to_string(),
             api_host: api_host.to_string(),
             folder_path: folder_path.map(|s| s.to_string()),
+            http,
         })
     }
 
@@ -1761,7 +3066,7 @@ impl PCloudProvider {
 
     async fn get_digest(&self) -> Result<String> {
         // pCloud requires a digest for authentication
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let url = format!("{}/getdigest", self.api_host);
         let response = client
             .get(&url)
@@ -1806,7 +3111,7 @@ impl StorageProvider for PCloudProvider {
 
         if folder_path != "/" {
             // Create folder if it doesn't exist (pCloud will ignore if it exists)
-            let client = reqwest::Client::new();
+            let client = &self.http;
             let create_url = format!("{}/createfolder", self.api_host);
             let _ = client
                 .get(&create_url)
@@ -1820,7 +3125,7 @@ impl StorageProvider for PCloudProvider {
         }
 
         // Upload file using multipart
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let upload_url = format!("{}/uploadfile", self.api_host);
 
         let form = reqwest::multipart::Form::new()
@@ -1828,10 +3133,7 @@ impl StorageProvider for PCloudProvider {
             .text("digest", digest)
             .text("path", folder_path.to_string())
             .text("filename", file_name.to_string())
-            .part(
-                "file",
-                reqwest::multipart::Part::bytes(data).file_name(file_name.to_string()),
-            );
+            .part("file", file_part(data, file_name, key));
 
         let response = client
             .post(&upload_url)
@@ -1858,7 +3160,7 @@ impl StorageProvider for PCloudProvider {
         let digest = self.get_digest().await?;
         let full_path = self.get_full_path(key);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let url = format!("{}/downloadfile", self.api_host);
         let response = client
             .get(&url)
@@ -1889,7 +3191,7 @@ impl StorageProvider for PCloudProvider {
         let digest = self.get_digest().await?;
         let folder_path = self.get_folder_path();
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let url = format!("{}/listfolder", self.api_host);
         let response = client
             .get(&url)
@@ -1903,41 +3205,14 @@ impl StorageProvider for PCloudProvider {
             .context("Failed to list pCloud files")?;
 
         let json: serde_json::Value = response.json().await?;
-        if json["result"].as_i64() != Some(0) {
-            let error = json["error"].as_str().unwrap_or("Unknown error");
-            return Err(anyhow::anyhow!("pCloud list failed: {}", error));
-        }
-
-        let mut items = Vec::new();
-        if let Some(metadata) = json.get("metadata") {
-            if let Some(files) = metadata.get("contents").and_then(|c| c.as_array()) {
-                for file in files {
-                    if let Some(name) = file["name"].as_str() {
-                        if name.starts_with(prefix) && file["isfolder"].as_i64() == Some(0) {
-                            items.push(BackupItem {
-                                key: name.to_string(),
-                                size: file["size"].as_u64().unwrap_or(0),
-                                last_modified: file["modified"].as_str().and_then(|s| {
-                                    // pCloud uses Unix timestamp
-                                    s.parse::<i64>()
-                                        .ok()
-                                        .and_then(|ts| DateTime::from_timestamp(ts, 0))
-                                }),
-                            });
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(items)
+        parse_pcloud_list(&json, prefix)
     }
 
     async fn delete(&self, key: &str) -> Result<()> {
         let digest = self.get_digest().await?;
         let full_path = self.get_full_path(key);
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
         let url = format!("{}/deletefile", self.api_host);
         let response = client
             .get(&url)
@@ -1960,12 +3235,48 @@ impl StorageProvider for PCloudProvider {
         Ok(())
     }
 
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let digest = self.get_digest().await?;
+        let full_path = self.get_full_path(key);
+
+        let client = &self.http;
+        let url = format!("{}/stat", self.api_host);
+        let response = client
+            .get(&url)
+            .query(&[
+                ("auth", &self.access_token),
+                ("digest", &digest),
+                ("path", &full_path),
+            ])
+            .send()
+            .await
+            .context("Failed to stat pCloud file")?;
+
+        let json: serde_json::Value = response.json().await?;
+        parse_pcloud_stat(&json)
+    }
+
+    async fn usage(&self) -> Result<StorageUsage> {
+        let digest = self.get_digest().await?;
+        let client = &self.http;
+        let url = format!("{}/userinfo", self.api_host);
+        let response = client
+            .get(&url)
+            .query(&[("auth", &self.access_token), ("digest", &digest)])
+            .send()
+            .await
+            .context("Failed to get pCloud user info")?;
+
+        let json: serde_json::Value = response.json().await?;
+        parse_pcloud_usage(&json)
+    }
+
     fn get_bucket(&self) -> &str {
         "pCloud"
     }
 }
 
-pub enum Provider {
+pub enum Backend {
     S3(S3Provider),
     Gcs(GCSProvider),
     Azure(AzureProvider),
@@ -1976,37 +3287,173 @@ pub enum Provider {
     Box(BoxProvider),
     Mega(MegaProvider),
     PCloud(PCloudProvider),
+    #[cfg(test)]
+    Mock(mock::MockProvider),
 }
 
-impl Provider {
-    pub async fn from_config(config: &StorageConfig) -> Result<Self> {
-        match config.provider.as_str() {
-            "s3" | "aws" | "contabo" | "digitalocean" | "wasabi" | "minio" | "r2" => {
-                let endpoint = match config.provider.as_str() {
-                    "aws" => format!("https://s3.{}.amazonaws.com", config.region),
-                    "digitalocean" => format!("https://{}.digitaloceanspaces.com", config.region),
-                    "wasabi" => format!("https://s3.{}.wasabisys.com", config.region),
-                    "r2" => format!(
-                        "https://{}.r2.cloudflarestorage.com",
-                        config.account_id.as_ref().unwrap_or(&"".to_string())
-                    ),
-                    _ => config.endpoint.clone(),
-                };
+/// The configured storage backend, with a tally of the traffic through it.
+pub struct Provider {
+    backend: Backend,
+    traffic: Arc<Mutex<Traffic>>,
+}
 
-                let provider = S3Provider::new(
-                    &endpoint,
-                    &config.region,
-                    &config.bucket,
-                    &config.access_key,
-                    &config.secret_key,
+impl From<Backend> for Provider {
+    fn from(backend: Backend) -> Self {
+        Self {
+            backend,
+            traffic: Default::default(),
+        }
+    }
+}
+
+/// Requests made and bytes moved through a provider, for estimating the bill
+/// on providers that charge for them. Each call counts once, however many
+/// pages or retries the provider needs for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Traffic {
+    pub bytes_uploaded: u64,
+    pub bytes_downloaded: u64,
+    pub puts: u64,
+    pub gets: u64,
+    pub heads: u64,
+    pub lists: u64,
+    pub deletes: u64,
+}
+
+impl Traffic {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The traffic since an `earlier` snapshot of the same counters.
+    pub fn since(&self, earlier: &Traffic) -> Traffic {
+        Traffic {
+            bytes_uploaded: self.bytes_uploaded - earlier.bytes_uploaded,
+            bytes_downloaded: self.bytes_downloaded - earlier.bytes_downloaded,
+            puts: self.puts - earlier.puts,
+            gets: self.gets - earlier.gets,
+            heads: self.heads - earlier.heads,
+            lists: self.lists - earlier.lists,
+            deletes: self.deletes - earlier.deletes,
+        }
+    }
+}
+
+impl std::fmt::Display for Traffic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2} MB uploaded, {:.2} MB downloaded; requests: {} put, {} get, {} head, {} list, {} delete",
+            self.bytes_uploaded as f64 / 1_048_576.0,
+            self.bytes_downloaded as f64 / 1_048_576.0,
+            self.puts,
+            self.gets,
+            self.heads,
+            self.lists,
+            self.deletes
+        )
+    }
+}
+
+/// An object stream that adds the bytes read to `Traffic::bytes_downloaded`.
+struct CountingStream {
+    inner: ObjectStream,
+    traffic: Arc<Mutex<Traffic>>,
+}
+
+impl tokio::io::AsyncRead for CountingStream {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = self.inner.as_mut().poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        self.traffic.lock().unwrap().bytes_downloaded += read;
+        poll
+    }
+}
+
+impl Provider {
+    pub async fn from_config(config: &StorageConfig) -> Result<Self> {
+        config
+            .validate_metadata()
+            .map_err(|e| ZestyError::config(format!("{:#}", e)))?;
+        if !config.metadata.is_empty()
+            && matches!(
+                config.provider.as_str(),
+                "onedrive" | "dropbox" | "box" | "mega" | "pcloud"
+            )
+        {
+            warn!(
+                "{} has no custom properties; storage.metadata is not attached to uploads",
+                config.provider
+            );
+        }
+        match config.provider.as_str() {
+            "s3" | "aws" | "contabo" | "digitalocean" | "wasabi" | "minio" | "r2" => {
+                if config.s3_transfer_acceleration
+                    && !matches!(config.provider.as_str(), "s3" | "aws")
+                {
+                    return Err(ZestyError::config(format!(
+                        "s3_transfer_acceleration is only available on AWS S3, not {}",
+                        config.provider
+                    ))
+                    .into());
+                }
+                let provider = S3Provider::new(
+                    &s3_endpoint(config),
+                    config.download_endpoint.as_deref(),
+                    &config.region,
+                    &config.bucket,
+                    &config.access_key,
+                    &config.secret_key,
+                    &network_config(config),
                 )
-                .await?;
-                Ok(Provider::S3(provider))
+                .await?
+                .with_metadata(&config.metadata);
+                Ok(Backend::S3(provider).into())
+            }
+            "b2" | "backblaze" | "b2s3" if config.provider == "b2s3" || config.s3_compatible => {
+                // B2's S3-compatible API takes the application key id/secret as access/secret keys
+                let access_key = if config.access_key.is_empty() {
+                    config.account_id.as_deref().context(
+                        "B2 application key id required (set as access_key or account_id)",
+                    )?
+                } else {
+                    &config.access_key
+                };
+                let secret_key = if config.secret_key.is_empty() {
+                    config.application_key.as_deref().context(
+                        "B2 application key required (set as secret_key or application_key)",
+                    )?
+                } else {
+                    &config.secret_key
+                };
+
+                let provider = S3Provider::new(
+                    &s3_endpoint(config),
+                    config.download_endpoint.as_deref(),
+                    &config.region,
+                    &config.bucket,
+                    access_key,
+                    secret_key,
+                    &network_config(config),
+                )
+                .await?
+                .with_metadata(&config.metadata);
+                Ok(Backend::S3(provider).into())
             }
             "gcs" | "google" => {
-                let provider =
-                    GCSProvider::new(&config.bucket, config.credentials_path.as_deref()).await?;
-                Ok(Provider::Gcs(provider))
+                let provider = GCSProvider::new(
+                    &config.bucket,
+                    config.credentials_path.as_deref(),
+                    &network_config(config),
+                )
+                .await?
+                .with_metadata(&config.metadata);
+                Ok(Backend::Gcs(provider).into())
             }
             "azure" => {
                 let provider = AzureProvider::new(
@@ -2014,11 +3461,13 @@ impl Provider {
                         .account_name
                         .as_ref()
                         .context("Azure account_name required")?,
-                    config.account_key.as_deref(),
+                    azure_auth(config, std::env::var("AZURE_STORAGE_ACCOUNT_KEY").ok())?,
                     &config.bucket,
+                    &network_config(config),
                 )
-                .await?;
-                Ok(Provider::Azure(provider))
+                .await?
+                .with_metadata(&config.metadata);
+                Ok(Backend::Azure(provider).into())
             }
             "googledrive" | "gdrive" => {
                 if config.access_key.is_empty() {
@@ -2029,9 +3478,11 @@ impl Provider {
                 let provider = GoogleDriveProvider::new(
                     &config.access_key,
                     config.bucket_id.as_deref(), // Use bucket_id for folder_id
+                    http_client(config)?,
                 )
-                .await?;
-                Ok(Provider::GoogleDrive(provider))
+                .await?
+                .with_metadata(&config.metadata);
+                Ok(Backend::GoogleDrive(provider).into())
             }
             "onedrive" => {
                 if config.access_key.is_empty() {
@@ -2042,9 +3493,10 @@ impl Provider {
                 let provider = OneDriveProvider::new(
                     &config.access_key,
                     config.bucket_id.as_deref(), // Use bucket_id for folder_path
+                    http_client(config)?,
                 )
                 .await?;
-                Ok(Provider::OneDrive(provider))
+                Ok(Backend::OneDrive(provider).into())
             }
             "dropbox" => {
                 if config.access_key.is_empty() {
@@ -2055,9 +3507,10 @@ impl Provider {
                 let provider = DropboxProvider::new(
                     &config.access_key,
                     config.bucket_id.as_deref(), // Use bucket_id for folder_path
+                    http_client(config)?,
                 )
                 .await?;
-                Ok(Provider::Dropbox(provider))
+                Ok(Backend::Dropbox(provider).into())
             }
             "box" => {
                 if config.access_key.is_empty() {
@@ -2068,9 +3521,10 @@ impl Provider {
                 let provider = BoxProvider::new(
                     &config.access_key,
                     config.bucket_id.as_deref(), // Use bucket_id for folder_id
+                    http_client(config)?,
                 )
                 .await?;
-                Ok(Provider::Box(provider))
+                Ok(Backend::Box(provider).into())
             }
             "mega" => {
                 let email = config
@@ -2087,7 +3541,7 @@ impl Provider {
                     config.bucket_id.as_deref(), // Use bucket_id for folder_path
                 )
                 .await?;
-                Ok(Provider::Mega(provider))
+                Ok(Backend::Mega(provider).into())
             }
             "pcloud" => {
                 if config.access_key.is_empty() {
@@ -2103,9 +3557,10 @@ impl Provider {
                     &config.access_key,
                     region,
                     config.bucket_id.as_deref(), // Use bucket_id for folder_path
+                    http_client(config)?,
                 )
                 .await?;
-                Ok(Provider::PCloud(provider))
+                Ok(Backend::PCloud(provider).into())
             }
             "b2" | "backblaze" => {
                 let provider = B2Provider::new(
@@ -2117,92 +3572,736 @@ impl Provider {
                         .application_key
                         .as_ref()
                         .context("B2 application_key required")?,
-                    config.bucket_id.as_ref().context("B2 bucket_id required")?,
+                    config.bucket_id.as_deref(),
                     &config.bucket,
+                    config.b2_verify_sha1,
+                    http_client(config)?,
                 )
-                .await?;
-                Ok(Provider::B2(provider))
+                .await?
+                .with_metadata(&config.metadata);
+                Ok(Backend::B2(provider).into())
             }
-            _ => Err(anyhow::anyhow!("Unknown provider: {}", config.provider)),
+            _ => Err(ZestyError::config(format!("Unknown provider: {}", config.provider)).into()),
+        }
+    }
+}
+
+impl Provider {
+    fn inner(&self) -> &dyn StorageProvider {
+        match &self.backend {
+            Backend::S3(p) => p,
+            Backend::Gcs(p) => p,
+            Backend::Azure(p) => p,
+            Backend::B2(p) => p,
+            Backend::GoogleDrive(p) => p,
+            Backend::OneDrive(p) => p,
+            Backend::Dropbox(p) => p,
+            Backend::Box(p) => p,
+            Backend::Mega(p) => p,
+            Backend::PCloud(p) => p,
+            #[cfg(test)]
+            Backend::Mock(p) => p,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match &self.backend {
+            Backend::S3(_) => "S3",
+            Backend::Gcs(_) => "GCS",
+            Backend::Azure(_) => "Azure",
+            Backend::B2(_) => "B2",
+            Backend::GoogleDrive(_) => "Google Drive",
+            Backend::OneDrive(_) => "OneDrive",
+            Backend::Dropbox(_) => "Dropbox",
+            Backend::Box(_) => "Box",
+            Backend::Mega(_) => "MEGA",
+            Backend::PCloud(_) => "pCloud",
+            #[cfg(test)]
+            Backend::Mock(_) => "mock",
         }
     }
+
+    /// Report a failed request as a `ZestyError::Provider` naming this provider.
+    fn error(&self, source: anyhow::Error) -> anyhow::Error {
+        ZestyError::provider(self.name(), source).into()
+    }
+
+    /// The traffic through this provider so far.
+    pub fn traffic(&self) -> Traffic {
+        *self.traffic.lock().unwrap()
+    }
+
+    fn count(&self, update: impl FnOnce(&mut Traffic)) {
+        update(&mut self.traffic.lock().unwrap());
+    }
+
+    #[cfg(test)]
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+}
+
+/// The size of a file just uploaded or downloaded, for the traffic tally.
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |meta| meta.len())
 }
 
 #[async_trait]
 impl StorageProvider for Provider {
     async fn upload(&self, key: &str, file_path: &Path) -> Result<()> {
-        match self {
-            Provider::S3(p) => p.upload(key, file_path).await,
-            Provider::Gcs(p) => p.upload(key, file_path).await,
-            Provider::Azure(p) => p.upload(key, file_path).await,
-            Provider::B2(p) => p.upload(key, file_path).await,
-            Provider::GoogleDrive(p) => p.upload(key, file_path).await,
-            Provider::OneDrive(p) => p.upload(key, file_path).await,
-            Provider::Dropbox(p) => p.upload(key, file_path).await,
-            Provider::Box(p) => p.upload(key, file_path).await,
-            Provider::Mega(p) => p.upload(key, file_path).await,
-            Provider::PCloud(p) => p.upload(key, file_path).await,
-        }
+        let result = self.inner().upload(key, file_path).await;
+        self.count(|t| {
+            t.puts += 1;
+            if result.is_ok() {
+                t.bytes_uploaded += file_size(file_path);
+            }
+        });
+        result.map_err(|e| self.error(e))
+    }
+
+    async fn upload_bytes(&self, key: &str, data: &[u8]) -> Result<()> {
+        let result = self.inner().upload_bytes(key, data).await;
+        self.count(|t| {
+            t.puts += 1;
+            if result.is_ok() {
+                t.bytes_uploaded += data.len() as u64;
+            }
+        });
+        result.map_err(|e| self.error(e))
     }
 
     async fn download(&self, key: &str, output_path: &Path) -> Result<()> {
-        match self {
-            Provider::S3(p) => p.download(key, output_path).await,
-            Provider::Gcs(p) => p.download(key, output_path).await,
-            Provider::Azure(p) => p.download(key, output_path).await,
-            Provider::B2(p) => p.download(key, output_path).await,
-            Provider::GoogleDrive(p) => p.download(key, output_path).await,
-            Provider::OneDrive(p) => p.download(key, output_path).await,
-            Provider::Dropbox(p) => p.download(key, output_path).await,
-            Provider::Box(p) => p.download(key, output_path).await,
-            Provider::Mega(p) => p.download(key, output_path).await,
-            Provider::PCloud(p) => p.download(key, output_path).await,
-        }
+        let result = self.inner().download(key, output_path).await;
+        self.count(|t| {
+            t.gets += 1;
+            if result.is_ok() {
+                t.bytes_downloaded += file_size(output_path);
+            }
+        });
+        result.map_err(|e| self.error(e))
     }
 
     async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
-        match self {
-            Provider::S3(p) => p.list(prefix).await,
-            Provider::Gcs(p) => p.list(prefix).await,
-            Provider::Azure(p) => p.list(prefix).await,
-            Provider::B2(p) => p.list(prefix).await,
-            Provider::GoogleDrive(p) => p.list(prefix).await,
-            Provider::OneDrive(p) => p.list(prefix).await,
-            Provider::Dropbox(p) => p.list(prefix).await,
-            Provider::Box(p) => p.list(prefix).await,
-            Provider::Mega(p) => p.list(prefix).await,
-            Provider::PCloud(p) => p.list(prefix).await,
-        }
+        let result = self.inner().list(prefix).await;
+        self.count(|t| t.lists += 1);
+        result.map_err(|e| self.error(e))
     }
 
     async fn delete(&self, key: &str) -> Result<()> {
-        match self {
-            Provider::S3(p) => p.delete(key).await,
-            Provider::Gcs(p) => p.delete(key).await,
-            Provider::Azure(p) => p.delete(key).await,
-            Provider::B2(p) => p.delete(key).await,
-            Provider::GoogleDrive(p) => p.delete(key).await,
-            Provider::OneDrive(p) => p.delete(key).await,
-            Provider::Dropbox(p) => p.delete(key).await,
-            Provider::Box(p) => p.delete(key).await,
-            Provider::Mega(p) => p.delete(key).await,
-            Provider::PCloud(p) => p.delete(key).await,
+        let result = self.inner().delete(key).await;
+        self.count(|t| t.deletes += 1);
+        result.map_err(|e| self.error(e))
+    }
+
+    async fn delete_if_unchanged(&self, key: &str, etag: &str) -> Result<()> {
+        let result = self.inner().delete_if_unchanged(key, etag).await;
+        self.count(|t| t.deletes += 1);
+        result.map_err(|e| self.error(e))
+    }
+
+    async fn upload_if_absent(&self, key: &str, file_path: &Path) -> Result<()> {
+        let result = self.inner().upload_if_absent(key, file_path).await;
+        self.count(|t| {
+            t.puts += 1;
+            if result.is_ok() {
+                t.bytes_uploaded += file_size(file_path);
+            }
+        });
+        result.map_err(|e| self.error(e))
+    }
+
+    async fn open_stream(&self, key: &str) -> Result<Option<ObjectStream>> {
+        let result = self.inner().open_stream(key).await;
+        match result {
+            Ok(Some(stream)) => {
+                self.count(|t| t.gets += 1);
+                Ok(Some(Box::pin(CountingStream {
+                    inner: stream,
+                    traffic: self.traffic.clone(),
+                })))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => {
+                self.count(|t| t.gets += 1);
+                Err(self.error(e))
+            }
         }
     }
 
+    async fn copy(&self, from_key: &str, to_key: &str) -> Result<bool> {
+        let result = self.inner().copy(from_key, to_key).await;
+        if !matches!(result, Ok(false)) {
+            self.count(|t| t.puts += 1);
+        }
+        result.map_err(|e| self.error(e))
+    }
+
+    async fn set_tags(&self, key: &str, tags: &[String]) -> Result<bool> {
+        let result = self.inner().set_tags(key, tags).await;
+        if !matches!(result, Ok(false)) {
+            self.count(|t| t.puts += 1);
+        }
+        result.map_err(|e| self.error(e))
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+        let result = self.inner().head(key).await;
+        self.count(|t| t.heads += 1);
+        result.map_err(|e| self.error(e))
+    }
+
+    async fn usage(&self) -> Result<StorageUsage> {
+        let result = self.inner().usage().await;
+        self.count(|t| t.gets += 1);
+        result.map_err(|e| self.error(e))
+    }
+
     fn get_bucket(&self) -> &str {
-        match self {
-            Provider::S3(p) => p.get_bucket(),
-            Provider::Gcs(p) => p.get_bucket(),
-            Provider::Azure(p) => p.get_bucket(),
-            Provider::B2(p) => p.get_bucket(),
-            Provider::GoogleDrive(p) => p.get_bucket(),
-            Provider::OneDrive(p) => p.get_bucket(),
-            Provider::Dropbox(p) => p.get_bucket(),
-            Provider::Box(p) => p.get_bucket(),
-            Provider::Mega(p) => p.get_bucket(),
-            Provider::PCloud(p) => p.get_bucket(),
+        self.inner().get_bucket()
+    }
+}
+
+/// An object_store object as a byte stream, fetched chunk by chunk.
+async fn object_store_stream(
+    store: &dyn object_store::ObjectStore,
+    key: &str,
+) -> Result<ObjectStream> {
+    use futures::TryStreamExt;
+
+    let stream = store
+        .get(&object_store::path::Path::from(key))
+        .await?
+        .into_stream()
+        .map_err(std::io::Error::other);
+    Ok(Box::pin(tokio_util::io::StreamReader::new(stream)))
+}
+
+/// Collect the objects of an S3 listing, calling `fetch` for the page after
+/// each continuation token. Paging stops once a page comes without a token,
+/// whatever its truncation flag says, and a token seen before is an error
+/// rather than an endless loop.
+async fn s3_list_pages<F, Fut>(mut fetch: F) -> Result<Vec<BackupItem>>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: std::future::Future<
+        Output = Result<aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output>,
+    >,
+{
+    let mut items = Vec::new();
+    let mut seen_tokens = std::collections::HashSet::new();
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        let response = fetch(continuation_token.take()).await?;
+
+        for obj in response.contents() {
+            if let Some(key) = obj.key() {
+                let item = BackupItem {
+                    key: key.to_string(),
+                    size: obj.size().unwrap_or(0) as u64,
+                    last_modified: obj.last_modified().map(|dt| {
+                        // Convert AWS DateTime to chrono DateTime
+                        let secs = dt.secs();
+                        DateTime::from_timestamp(secs, 0).unwrap_or_else(Utc::now)
+                    }),
+                    etag: obj.e_tag().map(|etag| etag.to_string()),
+                };
+                items.push(item);
+            }
+        }
+
+        match response.next_continuation_token() {
+            Some(token) if !token.is_empty() => {
+                if !seen_tokens.insert(token.to_string()) {
+                    anyhow::bail!(
+                        "S3 listing returned continuation token {:?} twice after {} objects",
+                        token,
+                        items.len()
+                    );
+                }
+                continuation_token = Some(token.to_string());
+            }
+            _ => {
+                if response.is_truncated() == Some(true) {
+                    warn!(
+                        "S3 listing was truncated without a continuation token; it may be incomplete ({} objects)",
+                        items.len()
+                    );
+                }
+                break;
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+/// Write `file_path` to `key` with `PutMode::Create`, so the store refuses to
+/// replace an existing object.
+async fn object_store_create(
+    store: &dyn object_store::ObjectStore,
+    key: &str,
+    file_path: &Path,
+    metadata: &BTreeMap<String, String>,
+) -> Result<()> {
+    let data = std::fs::read(file_path)
+        .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+    let options = object_store::PutOptions {
+        mode: object_store::PutMode::Create,
+        ..put_options(key, metadata)
+    };
+    match store
+        .put_opts(&object_store::path::Path::from(key), data.into(), options)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(
+            object_store::Error::AlreadyExists { .. } | object_store::Error::Precondition { .. },
+        ) => Err(AlreadyExists(key.to_string()).into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Copy a stream to a new file at `output_path`.
+async fn write_stream(mut stream: ObjectStream, output_path: &Path) -> Result<()> {
+    let mut file = tokio::fs::File::create(output_path)
+        .await
+        .with_context(|| format!("Failed to create output file: {}", output_path.display()))?;
+    tokio::io::copy(&mut stream, &mut file)
+        .await
+        .context("Failed to write to file")?;
+    Ok(())
+}
+
+async fn object_store_head(
+    store: &dyn object_store::ObjectStore,
+    key: &str,
+) -> Result<Option<ObjectMeta>> {
+    match store.head(&object_store::path::Path::from(key)).await {
+        Ok(meta) => Ok(Some(ObjectMeta {
+            size: meta.size,
+            last_modified: Some(meta.last_modified),
+            etag: meta.e_tag,
+        })),
+        Err(object_store::Error::NotFound { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Listing a folder that hasn't been created yet (nothing uploaded so far) gives
+// an empty list on every provider, like a missing prefix does on S3, so a
+// first-ever `list` or `status` works everywhere.
+
+/// The id and name of the bucket matching `bucket_id` or `bucket_name`
+/// (whichever is set) in a `b2_list_buckets` response.
+fn parse_b2_buckets(
+    status: reqwest::StatusCode,
+    json: &serde_json::Value,
+    bucket_id: &str,
+    bucket_name: &str,
+) -> Result<(String, String)> {
+    if !status.is_success() {
+        let error = json["message"].as_str().unwrap_or("Unknown error");
+        return Err(anyhow::anyhow!("B2 list_buckets failed: {}", error));
+    }
+
+    let bucket = json["buckets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|bucket| {
+            (!bucket_id.is_empty() && bucket["bucketId"] == bucket_id)
+                || (!bucket_name.is_empty() && bucket["bucketName"] == bucket_name)
+        });
+    match bucket.and_then(|b| Some((b["bucketId"].as_str()?, b["bucketName"].as_str()?))) {
+        Some((id, name)) => Ok((id.to_string(), name.to_string())),
+        None if bucket_name.is_empty() => {
+            Err(ZestyError::config(format!("No B2 bucket with bucket_id {}", bucket_id)).into())
         }
+        None => Err(ZestyError::config(format!("No B2 bucket named {}", bucket_name)).into()),
+    }
+}
+
+/// Parse a Google Drive `files` listing; a missing folder is a 404.
+fn parse_drive_list(
+    status: reqwest::StatusCode,
+    json: &serde_json::Value,
+    prefix: &str,
+) -> Result<Vec<BackupItem>> {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    if !status.is_success() {
+        let error = json["error"]["message"].as_str().unwrap_or("Unknown error");
+        return Err(anyhow::anyhow!("Google Drive list failed: {}", error));
+    }
+
+    Ok(json_items(&json["files"], prefix, |file| {
+        Some(BackupItem {
+            key: file["name"].as_str()?.to_string(),
+            size: file["size"]
+                .as_str()
+                .and_then(|s| s.parse::<u64>().ok())
+                .unwrap_or(0),
+            last_modified: parse_rfc3339(&file["modifiedTime"]),
+            etag: None,
+        })
+    }))
+}
+
+/// Parse a OneDrive `children` listing; a missing folder is a 404.
+fn parse_onedrive_list(
+    status: reqwest::StatusCode,
+    json: &serde_json::Value,
+    prefix: &str,
+) -> Result<Vec<BackupItem>> {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    if !status.is_success() {
+        let error = json["error"]["message"].as_str().unwrap_or("Unknown error");
+        return Err(anyhow::anyhow!("OneDrive list failed: {}", error));
+    }
+
+    Ok(json_items(&json["value"], prefix, |file| {
+        Some(BackupItem {
+            key: file["name"].as_str()?.to_string(),
+            size: file["size"].as_u64().unwrap_or(0),
+            last_modified: parse_rfc3339(&file["lastModifiedDateTime"]),
+            etag: None,
+        })
+    }))
+}
+
+/// Parse a Dropbox `list_folder` response; a missing folder is a 409 with a
+/// `path/not_found` error.
+fn parse_dropbox_list(
+    status: reqwest::StatusCode,
+    json: &serde_json::Value,
+    prefix: &str,
+) -> Result<Vec<BackupItem>> {
+    if !status.is_success() {
+        let error = json["error_summary"].as_str().unwrap_or("Unknown error");
+        if status == reqwest::StatusCode::CONFLICT && error.starts_with("path/not_found") {
+            return Ok(Vec::new());
+        }
+        return Err(anyhow::anyhow!("Dropbox list failed: {}", error));
+    }
+
+    Ok(json_items(&json["entries"], prefix, |entry| {
+        if entry[".tag"].as_str() != Some("file") {
+            return None;
+        }
+        Some(BackupItem {
+            key: entry["name"].as_str()?.to_string(),
+            size: entry["size"].as_u64().unwrap_or(0),
+            last_modified: parse_rfc3339(&entry["client_modified"]),
+            etag: None,
+        })
+    }))
+}
+
+/// Parse a Box folder `items` listing; a missing folder is a 404.
+fn parse_box_list(
+    status: reqwest::StatusCode,
+    json: &serde_json::Value,
+    prefix: &str,
+) -> Result<Vec<BackupItem>> {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    if !status.is_success() {
+        let error = json["message"].as_str().unwrap_or("Unknown error");
+        return Err(anyhow::anyhow!("Box list failed: {}", error));
+    }
+
+    Ok(json_items(&json["entries"], prefix, |entry| {
+        if entry["type"].as_str() != Some("file") {
+            return None;
+        }
+        Some(BackupItem {
+            key: entry["name"].as_str()?.to_string(),
+            size: entry["size"].as_u64().unwrap_or(0),
+            last_modified: parse_rfc3339(&entry["modified_at"]),
+            etag: None,
+        })
+    }))
+}
+
+/// Parse a pCloud `listfolder` response; a missing folder is error 2005.
+fn parse_pcloud_list(json: &serde_json::Value, prefix: &str) -> Result<Vec<BackupItem>> {
+    match json["result"].as_i64() {
+        Some(0) => {}
+        Some(2005) => return Ok(Vec::new()),
+        _ => {
+            let error = json["error"].as_str().unwrap_or("Unknown error");
+            return Err(anyhow::anyhow!("pCloud list failed: {}", error));
+        }
+    }
+
+    Ok(json_items(&json["metadata"]["contents"], prefix, |file| {
+        // A JSON boolean, though older responses used 0/1
+        let is_folder = &file["isfolder"];
+        if is_folder
+            .as_bool()
+            .or_else(|| is_folder.as_i64().map(|n| n != 0))
+            != Some(false)
+        {
+            return None;
+        }
+        Some(BackupItem {
+            key: file["name"].as_str()?.to_string(),
+            size: file["size"].as_u64().unwrap_or(0),
+            last_modified: parse_pcloud_time(&file["modified"]),
+            etag: None,
+        })
+    }))
+}
+
+/// A pCloud timestamp: an RFC 2822 date ("Thu, 21 Mar 2024 18:31:38 +0000")
+/// by default, or Unix seconds when requested with `timeformat=timestamp`.
+fn parse_pcloud_time(value: &serde_json::Value) -> Option<DateTime<Utc>> {
+    if let Some(seconds) = value.as_i64() {
+        return DateTime::from_timestamp(seconds, 0);
+    }
+    DateTime::parse_from_rfc2822(value.as_str()?)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// Parse `mega-ls -l` output (permissions, version, size, date, time, name);
+/// MEGAcmd reports a missing folder as "Couldn't find".
+fn parse_mega_ls(
+    success: bool,
+    stdout: &str,
+    stderr: &str,
+    prefix: &str,
+) -> Result<Vec<BackupItem>> {
+    if !success {
+        if stderr.contains("Couldn't find") || stderr.contains("Not found") {
+            return Ok(Vec::new());
+        }
+        return Err(anyhow::anyhow!("MEGA list failed: {}", stderr));
+    }
+
+    let mut items = Vec::new();
+    for line in stdout.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        // Only files (not directories)
+        if parts.len() >= 6 && parts[0].starts_with('-') {
+            let name = parts[5..].join(" ");
+            if name.starts_with(prefix) {
+                if let Ok(size) = parts[2].parse::<u64>() {
+                    items.push(BackupItem {
+                        key: name,
+                        size,
+                        last_modified: None, // MEGAcmd ls doesn't provide timestamps in simple format
+                        etag: None,
+                    });
+                }
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// The entries of a JSON array whose name starts with `prefix`, converted
+/// with `item` (which returns `None` to skip folders and the like).
+fn json_items<F>(entries: &serde_json::Value, prefix: &str, item: F) -> Vec<BackupItem>
+where
+    F: Fn(&serde_json::Value) -> Option<BackupItem>,
+{
+    entries
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(item)
+        .filter(|item| item.key.starts_with(prefix))
+        .collect()
+}
+
+fn parse_rfc3339(value: &serde_json::Value) -> Option<DateTime<Utc>> {
+    value
+        .as_str()
+        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// Parse a pCloud `stat` response. pCloud reports missing files as error 2009
+/// (2002 when a parent folder is missing).
+fn parse_pcloud_stat(json: &serde_json::Value) -> Result<Option<ObjectMeta>> {
+    match json["result"].as_i64() {
+        Some(0) => {}
+        Some(2002) | Some(2009) => return Ok(None),
+        _ => {
+            let error = json["error"].as_str().unwrap_or("Unknown error");
+            return Err(anyhow::anyhow!("pCloud stat failed: {}", error));
+        }
+    }
+
+    let metadata = &json["metadata"];
+    Ok(Some(ObjectMeta {
+        size: metadata["size"].as_u64().unwrap_or(0),
+        last_modified: parse_pcloud_time(&metadata["modified"]),
+        etag: metadata["hash"].as_u64().map(|hash| hash.to_string()),
+    }))
+}
+
+/// Parse the headers of a B2 `HEAD` of a file's download URL; a missing file
+/// is a 404.
+fn parse_b2_head(
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+) -> Result<Option<ObjectMeta>> {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(anyhow::anyhow!("B2 file info failed: {}", status));
+    }
+
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    Ok(Some(ObjectMeta {
+        size: header("content-length")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        last_modified: header("x-bz-upload-timestamp")
+            .and_then(|s| s.parse().ok())
+            .and_then(DateTime::from_timestamp_millis),
+        etag: header("x-bz-content-sha1")
+            .filter(|sha1| *sha1 != "none")
+            .map(|sha1| sha1.to_string()),
+    }))
+}
+
+/// Parse a OneDrive drive item; a missing file (or folder) is a 404.
+fn parse_onedrive_item(
+    status: reqwest::StatusCode,
+    json: &serde_json::Value,
+) -> Result<Option<ObjectMeta>> {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        let error = json["error"]["message"].as_str().unwrap_or("Unknown error");
+        return Err(anyhow::anyhow!("OneDrive file info failed: {}", error));
+    }
+    if json["file"].is_null() {
+        return Ok(None);
+    }
+
+    Ok(Some(ObjectMeta {
+        size: json["size"].as_u64().unwrap_or(0),
+        last_modified: parse_rfc3339(&json["lastModifiedDateTime"]),
+        etag: None,
+    }))
+}
+
+/// The ID of the file a Box upload preflight check conflicts with, or `None`
+/// if the name is free (a 200) or taken by a folder.
+fn parse_box_conflict(
+    status: reqwest::StatusCode,
+    json: &serde_json::Value,
+) -> Result<Option<String>> {
+    if status.is_success() {
+        return Ok(None);
+    }
+    if status != reqwest::StatusCode::CONFLICT {
+        let error = json["message"].as_str().unwrap_or("Unknown error");
+        return Err(anyhow::anyhow!("Box preflight check failed: {}", error));
+    }
+
+    // One conflicting item, though some responses wrap it in an array
+    let conflict = &json["context_info"]["conflicts"];
+    let conflict = conflict.get(0).unwrap_or(conflict);
+    if conflict["type"].as_str() != Some("file") {
+        return Ok(None);
+    }
+    conflict["id"]
+        .as_str()
+        .map(|id| Some(id.to_string()))
+        .context("Missing conflicting file ID in Box response")
+}
+
+/// Parse a Box file's info; a file deleted in the meantime is a 404.
+fn parse_box_file(
+    status: reqwest::StatusCode,
+    json: &serde_json::Value,
+) -> Result<Option<ObjectMeta>> {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        let error = json["message"].as_str().unwrap_or("Unknown error");
+        return Err(anyhow::anyhow!("Box file info failed: {}", error));
+    }
+
+    Ok(Some(ObjectMeta {
+        size: json["size"].as_u64().unwrap_or(0),
+        last_modified: parse_rfc3339(&json["modified_at"]),
+        etag: json["sha1"].as_str().map(|sha1| sha1.to_string()),
+    }))
+}
+
+/// Parse a Dropbox `users/get_space_usage` response. Team accounts report the
+/// shared allocation, which is the quota that actually applies.
+fn parse_dropbox_usage(json: &serde_json::Value) -> Result<StorageUsage> {
+    let allocation = &json["allocation"];
+    let used = match allocation[".tag"].as_str() {
+        Some("team") => allocation["used"].as_u64(),
+        _ => json["used"].as_u64(),
+    }
+    .context("Missing used in Dropbox space usage response")?;
+
+    Ok(StorageUsage {
+        used,
+        total: allocation["allocated"].as_u64(),
+    })
+}
+
+/// Parse a pCloud `userinfo` response.
+fn parse_pcloud_usage(json: &serde_json::Value) -> Result<StorageUsage> {
+    if json["result"].as_i64() != Some(0) {
+        let error = json["error"].as_str().unwrap_or("Unknown error");
+        return Err(anyhow::anyhow!("pCloud userinfo failed: {}", error));
+    }
+
+    Ok(StorageUsage {
+        used: json["usedquota"]
+            .as_u64()
+            .context("Missing usedquota in pCloud response")?,
+        total: json["quota"].as_u64(),
+    })
+}
+
+/// Parse a Google Drive `about` response. Drive returns the numbers as strings,
+/// and leaves out `limit` for unlimited storage.
+fn parse_drive_usage(json: &serde_json::Value) -> Result<StorageUsage> {
+    let quota = &json["storageQuota"];
+    let field = |name: &str| quota[name].as_str().and_then(|s| s.parse::<u64>().ok());
+
+    Ok(StorageUsage {
+        used: field("usage").context("Missing usage in Google Drive quota response")?,
+        total: field("limit"),
+    })
+}
+
+/// Resolve the endpoint URL for providers that go through `S3Provider`.
+/// An explicit `endpoint` is only used where the provider has no well-known one.
+pub fn s3_endpoint(config: &StorageConfig) -> String {
+    match config.provider.as_str() {
+        "s3" | "aws" if config.s3_transfer_acceleration => S3_ACCELERATE_ENDPOINT.to_string(),
+        "aws" => format!("https://s3.{}.amazonaws.com", config.region),
+        "digitalocean" => format!("https://{}.digitaloceanspaces.com", config.region),
+        "wasabi" => format!("https://s3.{}.wasabisys.com", config.region),
+        "r2" => format!(
+            "https://{}.r2.cloudflarestorage.com",
+            config.account_id.as_ref().unwrap_or(&"".to_string())
+        ),
+        "b2" | "backblaze" | "b2s3" if config.endpoint.is_empty() => {
+            format!("https://s3.{}.backblazeb2.com", config.region)
+        }
+        _ => config.endpoint.clone(),
     }
 }
 
@@ -2222,6 +4321,1866 @@ pub struct StorageConfig {
     pub application_key: Option<String>,
     pub bucket_id: Option<String>,
     pub credentials_path: Option<String>,
-    #[allow(dead_code)]
+    // Azure identity auth: SAS token, service principal or managed identity
+    pub sas_token: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
     pub tenant_id: Option<String>,
+    pub use_azure_cli: bool,
+    // Route B2 through its S3-compatible API instead of the native B2 API
+    pub s3_compatible: bool,
+    // Native B2: send each upload's SHA-1 for B2 to check
+    pub b2_verify_sha1: bool,
+    // Attached to every uploaded object, where the provider supports it
+    pub metadata: BTreeMap<String, String>,
+    // AWS only: upload and download through the S3 Transfer Acceleration endpoint
+    pub s3_transfer_acceleration: bool,
+    // S3 only: read objects through this endpoint instead
+    pub download_endpoint: Option<String>,
+    pub proxy: Option<ProxyConfig>,
+    pub tls: Option<TlsOptions>,
+    pub ca_bundle_path: Option<String>,
+    pub danger_accept_invalid_certs: bool,
+}
+
+impl StorageConfig {
+    /// Whether the provider is reached through the AWS SDK's S3 client.
+    pub fn uses_s3_api(&self) -> bool {
+        match self.provider.as_str() {
+            "s3" | "aws" | "contabo" | "digitalocean" | "wasabi" | "minio" | "r2" | "b2s3" => true,
+            "b2" | "backblaze" => self.s3_compatible,
+            _ => false,
+        }
+    }
+
+    /// Check `[storage.metadata]` against the provider's naming rules: S3
+    /// and GCS header names, C# identifiers on Azure, at most 9 short names
+    /// on native B2 (zesty-backup sends one itself), and Google Drive's
+    /// 124-byte property limit. Header values must be printable ASCII.
+    pub fn validate_metadata(&self) -> Result<()> {
+        if self.metadata.is_empty() {
+            return Ok(());
+        }
+        let header_name = |name: &str| {
+            name.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        };
+        let header_values = || {
+            for (name, value) in &self.metadata {
+                if !value.bytes().all(|b| (b' '..=b'~').contains(&b)) {
+                    anyhow::bail!(
+                        "storage.metadata.{}: values must be printable ASCII on {}",
+                        name,
+                        self.provider
+                    );
+                }
+            }
+            Ok(())
+        };
+        let total: usize = self.metadata.iter().map(|(k, v)| k.len() + v.len()).sum();
+        let check = |valid: bool, name: &str, rule: &str| {
+            if valid {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!(
+                    "storage.metadata.{}: {} names {}",
+                    name,
+                    self.provider,
+                    rule
+                ))
+            }
+        };
+        let native_b2 = matches!(self.provider.as_str(), "b2" | "backblaze") && !self.s3_compatible;
+        match self.provider.as_str() {
+            "b2" | "backblaze" if native_b2 => {
+                if self.metadata.len() > 9 {
+                    anyhow::bail!("storage.metadata: B2 allows at most 9 entries");
+                }
+                for name in self.metadata.keys() {
+                    check(
+                        !name.is_empty() && name.len() <= 50 && header_name(name),
+                        name,
+                        "must be 1-50 letters, digits, '-' or '_'",
+                    )?;
+                }
+                header_values()?;
+                if total > 7000 {
+                    anyhow::bail!("storage.metadata: B2 allows at most 7000 bytes");
+                }
+            }
+            "azure" => {
+                for name in self.metadata.keys() {
+                    let mut bytes = name.bytes();
+                    let valid = bytes
+                        .next()
+                        .is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+                        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_');
+                    check(
+                        valid,
+                        name,
+                        "must start with a letter or '_' and hold only letters, digits and '_'",
+                    )?;
+                }
+                header_values()?;
+                if total > 8192 {
+                    anyhow::bail!("storage.metadata: Azure allows at most 8 KiB");
+                }
+            }
+            "googledrive" | "gdrive" => {
+                if self.metadata.len() > 30 {
+                    anyhow::bail!("storage.metadata: Google Drive allows at most 30 properties");
+                }
+                for (name, value) in &self.metadata {
+                    check(
+                        !name.is_empty() && name.len() + value.len() <= 124,
+                        name,
+                        "plus their value must fit in 124 bytes",
+                    )?;
+                }
+            }
+            "gcs" | "google" => {
+                for name in self.metadata.keys() {
+                    check(
+                        !name.is_empty() && header_name(name),
+                        name,
+                        "must be letters, digits, '-' or '_'",
+                    )?;
+                }
+                header_values()?;
+                if total > 8192 {
+                    anyhow::bail!("storage.metadata: GCS allows at most 8 KiB");
+                }
+            }
+            "onedrive" | "dropbox" | "box" | "mega" | "pcloud" => {}
+            // S3 and the S3-compatible APIs
+            _ => {
+                for name in self.metadata.keys() {
+                    check(
+                        !name.is_empty() && header_name(name),
+                        name,
+                        "must be letters, digits, '-' or '_'",
+                    )?;
+                }
+                header_values()?;
+                if total > 2048 {
+                    anyhow::bail!("storage.metadata: S3 allows at most 2 KiB");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `[storage.proxy]`: proxy servers for all provider traffic. Unset fields fall
+/// back to the `HTTP_PROXY` / `HTTPS_PROXY` / `NO_PROXY` environment variables.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct ProxyConfig {
+    pub http: Option<String>,     // Proxy for http:// endpoints
+    pub https: Option<String>,    // Proxy for https:// endpoints
+    pub no_proxy: Option<String>, // Comma-separated hosts to reach directly
+}
+
+impl ProxyConfig {
+    /// Fill unset fields from environment variables, upper or lower case.
+    pub fn with_env_fallback<F>(&self, env: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let var = |name: &str| {
+            env(name)
+                .or_else(|| env(&name.to_lowercase()))
+                .filter(|value| !value.is_empty())
+        };
+        Self {
+            http: self.http.clone().or_else(|| var("HTTP_PROXY")),
+            https: self.https.clone().or_else(|| var("HTTPS_PROXY")),
+            no_proxy: self.no_proxy.clone().or_else(|| var("NO_PROXY")),
+        }
+    }
+
+    /// The proxy for an endpoint, picked by its scheme.
+    fn for_endpoint(&self, endpoint: &str) -> Option<&str> {
+        if endpoint.starts_with("http://") {
+            self.http.as_deref()
+        } else {
+            self.https.as_deref()
+        }
+    }
+}
+
+/// `[storage.tls]`: the TLS policy for all provider connections.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct TlsOptions {
+    pub min_version: Option<TlsVersion>, // Oldest TLS version to negotiate (default: 1.2)
+}
+
+impl TlsOptions {
+    /// Reject settings the HTTP clients can't honor.
+    pub fn validate(&self) -> Result<()> {
+        self.min_version.unwrap_or_default().reqwest().map(|_| ())
+    }
+}
+
+/// A TLS protocol version, as written in the config ("1.2").
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, JsonSchema)]
+pub enum TlsVersion {
+    #[serde(rename = "1.0")]
+    Tls1_0,
+    #[serde(rename = "1.1")]
+    Tls1_1,
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls1_2,
+    #[serde(rename = "1.3")]
+    Tls1_3,
+}
+
+impl TlsVersion {
+    /// The version as a reqwest client minimum. Its TLS backend can't require
+    /// 1.3, and neither can the rustls-based S3, GCS and Azure clients, which
+    /// always accept 1.2 and 1.3 (so any lower minimum holds for them anyway).
+    fn reqwest(self) -> Result<reqwest::tls::Version> {
+        Ok(match self {
+            TlsVersion::Tls1_0 => reqwest::tls::Version::TLS_1_0,
+            TlsVersion::Tls1_1 => reqwest::tls::Version::TLS_1_1,
+            TlsVersion::Tls1_2 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::Tls1_3 => {
+                return Err(ZestyError::config(
+                    "storage.tls.min_version = \"1.3\" isn't supported: the HTTP clients \
+                     can't refuse TLS 1.2; use \"1.2\"",
+                )
+                .into())
+            }
+        })
+    }
+}
+
+/// TLS settings: the minimum protocol version, and a private CA for
+/// self-hosted endpoints (e.g. MinIO).
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    pub min_version: TlsVersion,
+    pub ca_bundle_path: Option<String>, // PEM file of extra CA certificates to trust
+    pub danger_accept_invalid_certs: bool, // Skip certificate verification entirely
+}
+
+impl TlsConfig {
+    /// The minimum version for the reqwest client builder.
+    fn min_version(&self) -> Result<reqwest::tls::Version> {
+        self.min_version.reqwest()
+    }
+
+    /// The certificates in `ca_bundle_path`, checked to be valid PEM.
+    fn ca_bundle(&self) -> Result<Option<(Vec<u8>, Vec<reqwest::Certificate>)>> {
+        let Some(ref path) = self.ca_bundle_path else {
+            return Ok(None);
+        };
+        let pem =
+            std::fs::read(path).with_context(|| format!("Failed to read CA bundle: {}", path))?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Invalid CA bundle: {}", path))?;
+        if certificates.is_empty() {
+            anyhow::bail!("CA bundle has no certificates: {}", path);
+        }
+        Ok(Some((pem, certificates)))
+    }
+}
+
+/// Proxy and TLS settings shared by every provider's HTTP client.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkConfig {
+    pub proxy: ProxyConfig,
+    pub tls: TlsConfig,
+}
+
+impl NetworkConfig {
+    /// The reqwest client used by the B2 and consumer providers.
+    pub fn http_client(&self) -> Result<reqwest::Client> {
+        let no_proxy = self
+            .proxy
+            .no_proxy
+            .as_deref()
+            .and_then(reqwest::NoProxy::from_string);
+        let mut builder = reqwest::Client::builder();
+        if let Some(ref url) = self.proxy.http {
+            let proxy = reqwest::Proxy::http(url)
+                .with_context(|| format!("Invalid HTTP proxy: {}", url))?;
+            builder = builder.proxy(proxy.no_proxy(no_proxy.clone()));
+        }
+        if let Some(ref url) = self.proxy.https {
+            let proxy = reqwest::Proxy::https(url)
+                .with_context(|| format!("Invalid HTTPS proxy: {}", url))?;
+            builder = builder.proxy(proxy.no_proxy(no_proxy));
+        }
+        if let Some((_, certificates)) = self.tls.ca_bundle()? {
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        builder = builder
+            .min_tls_version(self.tls.min_version()?)
+            .danger_accept_invalid_certs(self.tls.danger_accept_invalid_certs);
+        builder.build().context("Failed to build HTTP client")
+    }
+
+    /// Client options for the object_store providers (GCS, Azure), which only
+    /// talk HTTPS.
+    fn client_options(&self) -> Result<object_store::ClientOptions> {
+        self.tls.min_version()?;
+        let mut options = object_store::ClientOptions::new();
+        if let Some(ref url) = self.proxy.https {
+            options = options.with_proxy_url(url);
+        }
+        if let Some(ref no_proxy) = self.proxy.no_proxy {
+            options = options.with_proxy_excludes(no_proxy);
+        }
+        if let Some((pem, _)) = self.tls.ca_bundle()? {
+            for certificate in object_store::Certificate::from_pem_bundle(&pem)? {
+                options = options.with_root_certificate(certificate);
+            }
+        }
+        Ok(options.with_allow_invalid_certificates(self.tls.danger_accept_invalid_certs))
+    }
+
+    /// An AWS SDK HTTP client going through the proxy for `endpoint` and
+    /// trusting the CA bundle. It's always built here rather than left to the
+    /// SDK default, so every S3 connection uses rustls with TLS 1.2 or later.
+    fn aws_http_client(&self, endpoint: &str) -> Result<aws_sdk_s3::config::SharedHttpClient> {
+        use aws_smithy_http_client::{proxy::ProxyConfig as AwsProxyConfig, tls, Connector};
+
+        self.tls.min_version()?;
+        if self.tls.danger_accept_invalid_certs {
+            // The SDK's TLS connector has no way to turn verification off
+            anyhow::bail!(
+                "danger_accept_invalid_certs is not supported for S3 endpoints; \
+                set ca_bundle_path to trust the endpoint's CA instead"
+            );
+        }
+        let proxy_url = self.proxy.for_endpoint(endpoint);
+        let ca_bundle = self.tls.ca_bundle()?;
+        let aws_proxy = match proxy_url {
+            Some(proxy_url) => {
+                let mut aws_proxy = if endpoint.starts_with("http://") {
+                    AwsProxyConfig::http(proxy_url)
+                } else {
+                    AwsProxyConfig::https(proxy_url)
+                }
+                .with_context(|| format!("Invalid proxy: {}", proxy_url))?;
+                if let Some(ref no_proxy) = self.proxy.no_proxy {
+                    aws_proxy = aws_proxy.no_proxy(no_proxy);
+                }
+                Some(aws_proxy)
+            }
+            None => None,
+        };
+        let mut trust_store = tls::TrustStore::default();
+        if let Some((pem, _)) = ca_bundle {
+            trust_store = trust_store.with_pem_certificate(pem);
+        }
+        let tls_context = tls::TlsContext::builder()
+            .with_trust_store(trust_store)
+            .build()
+            .context("Invalid TLS settings")?;
+
+        Ok(
+            aws_smithy_http_client::Builder::new().build_with_connector_fn(
+                move |settings, components| {
+                    let mut builder = Connector::builder();
+                    if let Some(ref aws_proxy) = aws_proxy {
+                        builder = builder.proxy_config(aws_proxy.clone());
+                    }
+                    builder.set_connector_settings(settings.cloned());
+                    if let Some(components) = components {
+                        builder.set_sleep_impl(components.sleep_impl());
+                    }
+                    builder
+                        .tls_provider(tls::Provider::Rustls(
+                            tls::rustls_provider::CryptoMode::AwsLc,
+                        ))
+                        .tls_context(tls_context.clone())
+                        .build()
+                },
+            ),
+        )
+    }
+}
+
+fn network_config(config: &StorageConfig) -> NetworkConfig {
+    let proxy = config
+        .proxy
+        .clone()
+        .unwrap_or_default()
+        .with_env_fallback(|name| std::env::var(name).ok());
+    if config.danger_accept_invalid_certs {
+        warn!("TLS certificate verification is disabled (danger_accept_invalid_certs)");
+    }
+    NetworkConfig {
+        proxy,
+        tls: TlsConfig {
+            min_version: config
+                .tls
+                .as_ref()
+                .and_then(|tls| tls.min_version)
+                .unwrap_or_default(),
+            ca_bundle_path: config.ca_bundle_path.clone(),
+            danger_accept_invalid_certs: config.danger_accept_invalid_certs,
+        },
+    }
+}
+
+fn http_client(config: &StorageConfig) -> Result<reqwest::Client> {
+    network_config(config).http_client()
+}
+
+// In-memory provider for exercising BackupManager without a network
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use std::collections::{BTreeMap, HashSet};
+    use std::sync::Mutex;
+
+    struct MockObject {
+        data: Vec<u8>,
+        last_modified: DateTime<Utc>,
+    }
+
+    impl MockObject {
+        /// A content hash, standing in for the ETag of a real store.
+        fn etag(&self) -> String {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            self.data.hash(&mut hasher);
+            format!("{:016x}", hasher.finish())
+        }
+    }
+
+    #[derive(Default)]
+    pub struct MockProvider {
+        objects: Mutex<BTreeMap<String, MockObject>>,
+        deleted: Mutex<Vec<String>>,
+        streamed: Mutex<Vec<String>>,
+        list_error: Mutex<Option<String>>,
+        replace_after_list: Mutex<Option<(String, Vec<u8>)>>,
+        // Multipart uploads in progress, by upload id, with their parts
+        multipart: Mutex<BTreeMap<String, BTreeMap<u32, Vec<u8>>>>,
+        // Part numbers that fail once, and whether the failure is permanent
+        failing_parts: Mutex<BTreeMap<u32, bool>>,
+        uploaded_parts: Mutex<Vec<u32>>,
+        aborted: Mutex<Vec<String>>,
+        failing_deletes: Mutex<HashSet<String>>,
+    }
+
+    impl MockProvider {
+        pub fn insert(&self, key: &str, data: &[u8], last_modified: DateTime<Utc>) {
+            self.objects.lock().unwrap().insert(
+                key.to_string(),
+                MockObject {
+                    data: data.to_vec(),
+                    last_modified,
+                },
+            );
+        }
+
+        /// Make every `list` call fail with `error`.
+        pub fn fail_list(&self, error: &str) {
+            *self.list_error.lock().unwrap() = Some(error.to_string());
+        }
+
+        /// Upload `data` as `key` right after the next `list`, like a
+        /// concurrent writer replacing an object just listed.
+        pub fn replace_after_list(&self, key: &str, data: &[u8]) {
+            *self.replace_after_list.lock().unwrap() = Some((key.to_string(), data.to_vec()));
+        }
+
+        pub fn keys(&self) -> Vec<String> {
+            self.objects.lock().unwrap().keys().cloned().collect()
+        }
+
+        pub fn deleted(&self) -> Vec<String> {
+            self.deleted.lock().unwrap().clone()
+        }
+
+        /// Make every delete of `key` fail.
+        pub fn fail_delete(&self, key: &str) {
+            self.failing_deletes.lock().unwrap().insert(key.to_string());
+        }
+
+        fn check_delete(&self, key: &str) -> Result<()> {
+            if self.failing_deletes.lock().unwrap().contains(key) {
+                return Err(anyhow::anyhow!("403 Forbidden: {}", key));
+            }
+            Ok(())
+        }
+
+        /// Make the next upload of part `number` fail, permanently or not.
+        pub fn fail_part(&self, number: u32, permanent: bool) {
+            self.failing_parts.lock().unwrap().insert(number, permanent);
+        }
+
+        /// Part numbers uploaded successfully, in order.
+        pub fn uploaded_parts(&self) -> Vec<u32> {
+            self.uploaded_parts.lock().unwrap().clone()
+        }
+
+        /// Ids of aborted multipart uploads.
+        pub fn aborted(&self) -> Vec<String> {
+            self.aborted.lock().unwrap().clone()
+        }
+
+        /// Ids of multipart uploads neither completed nor aborted.
+        pub fn pending_uploads(&self) -> Vec<String> {
+            self.multipart.lock().unwrap().keys().cloned().collect()
+        }
+
+        /// Keys read through `open_stream`.
+        pub fn streamed(&self) -> Vec<String> {
+            self.streamed.lock().unwrap().clone()
+        }
+
+        pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+            self.objects
+                .lock()
+                .unwrap()
+                .get(key)
+                .map(|object| object.data.clone())
+        }
+    }
+
+    #[async_trait]
+    impl MultipartUpload for MockProvider {
+        async fn start_multipart(&self, key: &str) -> Result<String> {
+            let mut multipart = self.multipart.lock().unwrap();
+            let upload_id = format!("{}#{}", key, multipart.len() + self.aborted().len());
+            multipart.insert(upload_id.clone(), BTreeMap::new());
+            Ok(upload_id)
+        }
+
+        async fn upload_part(
+            &self,
+            _key: &str,
+            upload_id: &str,
+            number: u32,
+            data: Vec<u8>,
+        ) -> Result<String> {
+            match self.failing_parts.lock().unwrap().remove(&number) {
+                Some(true) => {
+                    return Err(
+                        PermanentUploadFailure(format!("No such upload: {}", upload_id)).into(),
+                    )
+                }
+                Some(false) => return Err(anyhow::anyhow!("Connection reset")),
+                None => {}
+            }
+            let mut multipart = self.multipart.lock().unwrap();
+            let parts = multipart.get_mut(upload_id).context("No such upload")?;
+            let etag = format!("etag-{}-{}", number, data.len());
+            parts.insert(number, data);
+            self.uploaded_parts.lock().unwrap().push(number);
+            Ok(etag)
+        }
+
+        async fn complete_multipart(
+            &self,
+            key: &str,
+            upload_id: &str,
+            parts: &[UploadedPart],
+        ) -> Result<()> {
+            let uploaded = self
+                .multipart
+                .lock()
+                .unwrap()
+                .remove(upload_id)
+                .context("No such upload")?;
+            let numbers: Vec<u32> = parts.iter().map(|part| part.number).collect();
+            anyhow::ensure!(
+                numbers == uploaded.keys().copied().collect::<Vec<_>>(),
+                "Parts don't match the upload"
+            );
+            self.insert(
+                key,
+                &uploaded.into_values().flatten().collect::<Vec<_>>(),
+                Utc::now(),
+            );
+            Ok(())
+        }
+
+        async fn abort_multipart(&self, _key: &str, upload_id: &str) -> Result<()> {
+            self.multipart.lock().unwrap().remove(upload_id);
+            self.aborted.lock().unwrap().push(upload_id.to_string());
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl StorageProvider for MockProvider {
+        async fn upload(&self, key: &str, file_path: &Path) -> Result<()> {
+            let data = std::fs::read(file_path)?;
+            self.insert(key, &data, Utc::now());
+            Ok(())
+        }
+
+        async fn upload_bytes(&self, key: &str, data: &[u8]) -> Result<()> {
+            self.insert(key, data, Utc::now());
+            Ok(())
+        }
+
+        async fn upload_if_absent(&self, key: &str, file_path: &Path) -> Result<()> {
+            let data = std::fs::read(file_path)?;
+            let mut objects = self.objects.lock().unwrap();
+            if objects.contains_key(key) {
+                return Err(AlreadyExists(key.to_string()).into());
+            }
+            objects.insert(
+                key.to_string(),
+                MockObject {
+                    data,
+                    last_modified: Utc::now(),
+                },
+            );
+            Ok(())
+        }
+
+        async fn download(&self, key: &str, output_path: &Path) -> Result<()> {
+            let objects = self.objects.lock().unwrap();
+            let object = objects.get(key).context("Object not found")?;
+            std::fs::write(output_path, &object.data)?;
+            Ok(())
+        }
+
+        async fn open_stream(&self, key: &str) -> Result<Option<ObjectStream>> {
+            let data = self.get(key).context("Object not found")?;
+            self.streamed.lock().unwrap().push(key.to_string());
+            Ok(Some(Box::pin(std::io::Cursor::new(data))))
+        }
+
+        async fn list(&self, prefix: &str) -> Result<Vec<BackupItem>> {
+            if let Some(ref error) = *self.list_error.lock().unwrap() {
+                return Err(anyhow::anyhow!("{}", error));
+            }
+            let items = self
+                .objects
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .map(|(key, object)| BackupItem {
+                    key: key.clone(),
+                    size: object.data.len() as u64,
+                    last_modified: Some(object.last_modified),
+                    etag: Some(object.etag()),
+                })
+                .collect();
+            if let Some((key, data)) = self.replace_after_list.lock().unwrap().take() {
+                self.insert(&key, &data, Utc::now());
+            }
+            Ok(items)
+        }
+
+        async fn head(&self, key: &str) -> Result<Option<ObjectMeta>> {
+            Ok(self
+                .objects
+                .lock()
+                .unwrap()
+                .get(key)
+                .map(|object| ObjectMeta {
+                    size: object.data.len() as u64,
+                    last_modified: Some(object.last_modified),
+                    etag: Some(object.etag()),
+                }))
+        }
+
+        async fn delete(&self, key: &str) -> Result<()> {
+            self.check_delete(key)?;
+            self.objects.lock().unwrap().remove(key);
+            self.deleted.lock().unwrap().push(key.to_string());
+            Ok(())
+        }
+
+        async fn delete_if_unchanged(&self, key: &str, etag: &str) -> Result<()> {
+            self.check_delete(key)?;
+            let mut objects = self.objects.lock().unwrap();
+            if objects.get(key).is_some_and(|object| object.etag() != etag) {
+                return Err(Changed(key.to_string()).into());
+            }
+            objects.remove(key);
+            self.deleted.lock().unwrap().push(key.to_string());
+            Ok(())
+        }
+
+        async fn copy(&self, from_key: &str, to_key: &str) -> Result<bool> {
+            let data = self
+                .objects
+                .lock()
+                .unwrap()
+                .get(from_key)
+                .context("Object not found")?
+                .data
+                .clone();
+            self.insert(to_key, &data, Utc::now());
+            Ok(true)
+        }
+
+        fn get_bucket(&self) -> &str {
+            "mock"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage_config(provider: &str, region: &str) -> StorageConfig {
+        StorageConfig {
+            provider: provider.to_string(),
+            endpoint: String::new(),
+            region: region.to_string(),
+            bucket: "test-bucket".to_string(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            account_id: None,
+            account_name: None,
+            account_key: None,
+            application_key: None,
+            bucket_id: None,
+            credentials_path: None,
+            sas_token: None,
+            client_id: None,
+            client_secret: None,
+            tenant_id: None,
+            use_azure_cli: false,
+            s3_compatible: false,
+            b2_verify_sha1: true,
+            metadata: BTreeMap::new(),
+            s3_transfer_acceleration: false,
+            download_endpoint: None,
+            proxy: None,
+            tls: None,
+            ca_bundle_path: None,
+            danger_accept_invalid_certs: false,
+        }
+    }
+
+    /// Test the Azure auth method is picked from the configured fields, preferring
+    /// SAS tokens and identities over shared keys
+    #[tokio::test]
+    async fn test_azure_auth_resolution() {
+        let env_key = || Some("ZW52LWtleQ==".to_string());
+        let mut config = storage_config("azure", "");
+        assert_eq!(
+            azure_auth(&config, None).unwrap(),
+            AzureAuth::ManagedIdentity { client_id: None }
+        );
+        assert_eq!(
+            azure_auth(&config, env_key()).unwrap(),
+            AzureAuth::SharedKey("ZW52LWtleQ==".to_string())
+        );
+
+        config.account_key = Some("YWNjb3VudC1rZXk=".to_string());
+        assert_eq!(
+            azure_auth(&config, env_key()).unwrap(),
+            AzureAuth::SharedKey("YWNjb3VudC1rZXk=".to_string())
+        );
+
+        config.use_azure_cli = true;
+        assert_eq!(azure_auth(&config, None).unwrap(), AzureAuth::AzureCli);
+
+        config.client_secret = Some("secret".to_string());
+        assert!(azure_auth(&config, None).is_err());
+        config.client_id = Some("app-id".to_string());
+        config.tenant_id = Some("tenant".to_string());
+        assert_eq!(
+            azure_auth(&config, None).unwrap(),
+            AzureAuth::ClientSecret {
+                client_id: "app-id".to_string(),
+                client_secret: "secret".to_string(),
+                tenant_id: "tenant".to_string(),
+            }
+        );
+
+        config.sas_token = Some("?sv=2022-11-02&sig=abc%3D".to_string());
+        assert_eq!(
+            azure_auth(&config, None).unwrap(),
+            AzureAuth::Sas("sv=2022-11-02&sig=abc%3D".to_string())
+        );
+
+        let identity_only = StorageConfig {
+            client_id: Some("identity-id".to_string()),
+            ..storage_config("azure", "")
+        };
+        let auth = azure_auth(&identity_only, None).unwrap();
+        assert_eq!(
+            auth,
+            AzureAuth::ManagedIdentity {
+                client_id: Some("identity-id".to_string())
+            }
+        );
+        for auth in [
+            auth,
+            azure_auth(&config, None).unwrap(),
+            AzureAuth::AzureCli,
+        ] {
+            AzureProvider::new("account", auth, "backups", &NetworkConfig::default())
+                .await
+                .unwrap();
+        }
+    }
+
+    /// Test B2 S3-compatible endpoint resolution from the region
+    #[test]
+    fn test_b2_s3_endpoint() {
+        let mut config = storage_config("b2", "us-west-004");
+        config.s3_compatible = true;
+        assert_eq!(
+            s3_endpoint(&config),
+            "https://s3.us-west-004.backblazeb2.com"
+        );
+
+        let config = storage_config("b2s3", "eu-central-003");
+        assert_eq!(
+            s3_endpoint(&config),
+            "https://s3.eu-central-003.backblazeb2.com"
+        );
+
+        // An explicit endpoint wins
+        let mut config = storage_config("b2s3", "us-west-004");
+        config.endpoint = "https://b2.example.com".to_string();
+        assert_eq!(s3_endpoint(&config), "https://b2.example.com");
+    }
+
+    /// Test parsing Dropbox space usage for individual and team accounts
+    #[test]
+    fn test_parse_dropbox_usage() {
+        let individual = serde_json::json!({
+            "used": 314572800u64,
+            "allocation": { ".tag": "individual", "allocated": 2147483648u64 }
+        });
+        assert_eq!(
+            parse_dropbox_usage(&individual).unwrap(),
+            StorageUsage {
+                used: 314572800,
+                total: Some(2147483648)
+            }
+        );
+
+        let team = serde_json::json!({
+            "used": 1024,
+            "allocation": {
+                ".tag": "team",
+                "used": 5368709120u64,
+                "allocated": 5497558138880u64
+            }
+        });
+        assert_eq!(
+            parse_dropbox_usage(&team).unwrap(),
+            StorageUsage {
+                used: 5368709120,
+                total: Some(5497558138880)
+            }
+        );
+
+        assert!(parse_dropbox_usage(&serde_json::json!({})).is_err());
+    }
+
+    /// Test parsing pCloud userinfo quota and error responses
+    #[test]
+    fn test_parse_pcloud_usage() {
+        let info = serde_json::json!({
+            "result": 0,
+            "email": "user@example.com",
+            "usedquota": 52428800,
+            "quota": 10737418240u64
+        });
+        assert_eq!(
+            parse_pcloud_usage(&info).unwrap(),
+            StorageUsage {
+                used: 52428800,
+                total: Some(10737418240)
+            }
+        );
+
+        let error = serde_json::json!({ "result": 2000, "error": "Log in failed." });
+        let err = parse_pcloud_usage(&error).unwrap_err();
+        assert!(err.to_string().contains("Log in failed."));
+    }
+
+    /// Test the default usage sums the sizes of listed objects
+    #[tokio::test]
+    async fn test_default_usage_sums_list() {
+        let provider = mock::MockProvider::default();
+        provider.insert("backups/a.tar.zst", &[0; 100], Utc::now());
+        provider.insert("backups/b.tar.zst", &[0; 50], Utc::now());
+        assert_eq!(
+            provider.usage().await.unwrap(),
+            StorageUsage {
+                used: 150,
+                total: None
+            }
+        );
+    }
+
+    /// Test head finds present keys and reports absent ones as None
+    #[tokio::test]
+    async fn test_head_present_and_absent() {
+        let provider = mock::MockProvider::default();
+        let modified = Utc::now();
+        provider.insert("backups/a.tar.zst", &[0; 42], modified);
+
+        let meta = provider.head("backups/a.tar.zst").await.unwrap().unwrap();
+        assert_eq!(meta.size, 42);
+        assert_eq!(meta.last_modified, Some(modified));
+        assert!(provider.head("backups/b.tar.zst").await.unwrap().is_none());
+        assert!(provider.head("a.tar.zst").await.unwrap().is_none());
+    }
+
+    /// Test parsing pCloud stat responses for present and missing files
+    #[test]
+    fn test_parse_pcloud_stat() {
+        let present = serde_json::json!({
+            "result": 0,
+            "metadata": {
+                "name": "backup.tar.zst",
+                "size": 1024,
+                "modified": "Thu, 21 Mar 2024 18:31:38 +0000",
+                "hash": 6234556717036738000u64,
+                "isfolder": false
+            }
+        });
+        let meta = parse_pcloud_stat(&present).unwrap().unwrap();
+        assert_eq!(meta.size, 1024);
+        assert_eq!(meta.last_modified, DateTime::from_timestamp(1711045898, 0));
+        assert_eq!(meta.etag.as_deref(), Some("6234556717036738000"));
+
+        let missing = serde_json::json!({ "result": 2009, "error": "File not found." });
+        assert!(parse_pcloud_stat(&missing).unwrap().is_none());
+
+        let error = serde_json::json!({ "result": 1000, "error": "Log in required." });
+        assert!(parse_pcloud_stat(&error).is_err());
+    }
+
+    /// Test parsing the B2, OneDrive and Box single-file lookups for present
+    /// and missing files, and that failed requests are errors
+    #[test]
+    fn test_parse_file_info() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        use reqwest::StatusCode;
+        use serde_json::json;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("content-length", HeaderValue::from_static("1024"));
+        headers.insert(
+            "x-bz-upload-timestamp",
+            HeaderValue::from_static("1711045898000"),
+        );
+        headers.insert("x-bz-content-sha1", HeaderValue::from_static("none"));
+        let meta = parse_b2_head(StatusCode::OK, &headers).unwrap().unwrap();
+        assert_eq!(meta.size, 1024);
+        assert_eq!(meta.last_modified, DateTime::from_timestamp(1711045898, 0));
+        assert_eq!(meta.etag, None);
+        let empty = HeaderMap::new();
+        assert!(parse_b2_head(StatusCode::NOT_FOUND, &empty)
+            .unwrap()
+            .is_none());
+        assert!(parse_b2_head(StatusCode::UNAUTHORIZED, &empty).is_err());
+
+        let item = json!({
+            "name": "backup.tar.zst",
+            "size": 2048,
+            "lastModifiedDateTime": "2024-03-21T18:31:38Z",
+            "file": { "mimeType": "application/zstd" }
+        });
+        let meta = parse_onedrive_item(StatusCode::OK, &item).unwrap().unwrap();
+        assert_eq!(meta.size, 2048);
+        assert_eq!(meta.last_modified, DateTime::from_timestamp(1711045898, 0));
+        let folder = json!({ "name": "backup.tar.zst", "folder": { "childCount": 0 } });
+        assert!(parse_onedrive_item(StatusCode::OK, &folder)
+            .unwrap()
+            .is_none());
+        let missing = json!({ "error": { "code": "itemNotFound", "message": "Not found" } });
+        assert!(parse_onedrive_item(StatusCode::NOT_FOUND, &missing)
+            .unwrap()
+            .is_none());
+        assert!(parse_onedrive_item(StatusCode::UNAUTHORIZED, &missing).is_err());
+
+        let conflict = json!({
+            "code": "item_name_in_use",
+            "context_info": { "conflicts": { "type": "file", "id": "12345" } }
+        });
+        assert_eq!(
+            parse_box_conflict(StatusCode::CONFLICT, &conflict).unwrap(),
+            Some("12345".to_string())
+        );
+        assert!(parse_box_conflict(StatusCode::OK, &json!({}))
+            .unwrap()
+            .is_none());
+        let forbidden = json!({ "message": "Access denied" });
+        assert!(parse_box_conflict(StatusCode::FORBIDDEN, &forbidden).is_err());
+        let file = json!({
+            "type": "file",
+            "id": "12345",
+            "size": 4096,
+            "modified_at": "2024-03-21T18:31:38Z",
+            "sha1": "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+        });
+        let meta = parse_box_file(StatusCode::OK, &file).unwrap().unwrap();
+        assert_eq!(meta.size, 4096);
+        assert_eq!(
+            meta.etag.as_deref(),
+            Some("aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d")
+        );
+        assert!(parse_box_file(StatusCode::NOT_FOUND, &json!({}))
+            .unwrap()
+            .is_none());
+    }
+
+    /// Test the provider HTTP client sends requests through the configured proxy
+    #[tokio::test]
+    async fn test_http_client_uses_proxy() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_url = format!("http://{}", listener.local_addr().unwrap());
+        let proxy_server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        let proxy = ProxyConfig {
+            http: Some(proxy_url),
+            ..Default::default()
+        };
+        let network = NetworkConfig {
+            proxy,
+            ..Default::default()
+        };
+        let client = network.http_client().unwrap();
+        let response = client
+            .get("http://backups.example.invalid/ping")
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+
+        let request = proxy_server.await.unwrap();
+        assert!(request.starts_with("GET http://backups.example.invalid/ping HTTP/1.1"));
+    }
+
+    /// Test the CA bundle and insecure flag reach every client builder
+    #[test]
+    fn test_tls_settings() {
+        const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIBgTCCASegAwIBAgIUImJcrYgObC3q1781vY/gVZwsmnkwCgYIKoZIzj0EAwIw
+FTETMBEGA1UEAwwKbWluaW8udGVzdDAgFw0yNjEwMTcwMzIwNDhaGA8yMTI2MDky
+MzAzMjA0OFowFTETMBEGA1UEAwwKbWluaW8udGVzdDBZMBMGByqGSM49AgEGCCqG
+SM49AwEHA0IABKpxlrC7WITrD/XKQ8Xia59wRZiTRIu1h1166/HS5bMRDlIcC5Jw
+xVoZ1C3zBGO3MFF838No12my6CG5LfHJNzajUzBRMB0GA1UdDgQWBBRvxGE5CGC7
+czAO1s4o2v/eJL8XtzAfBgNVHSMEGDAWgBRvxGE5CGC7czAO1s4o2v/eJL8XtzAP
+BgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIQD2JIk1sH9qM22/POdG
+duXBdBrU+zSLDHv9qermsmyrlAIgeqjYDpv9gjgrTEwAHSRiUQUNBDn3FRmqlsIl
+WOLDd9A=
+-----END CERTIFICATE-----
+";
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let bundle = temp_dir.path().join("ca.pem");
+        std::fs::write(&bundle, CERTIFICATE).unwrap();
+        let endpoint = "https://minio.internal:9000";
+
+        let mut config = storage_config("minio", "us-east-1");
+        network_config(&config).aws_http_client(endpoint).unwrap();
+
+        config.ca_bundle_path = Some(bundle.to_string_lossy().to_string());
+        let network = network_config(&config);
+        let (_, certificates) = network.tls.ca_bundle().unwrap().unwrap();
+        assert_eq!(certificates.len(), 1);
+        network.http_client().unwrap();
+        network.client_options().unwrap();
+        network.aws_http_client(endpoint).unwrap();
+
+        config.danger_accept_invalid_certs = true;
+        let network = network_config(&config);
+        assert!(network.tls.danger_accept_invalid_certs);
+        network.http_client().unwrap();
+        network.client_options().unwrap();
+        let error = network.aws_http_client(endpoint).unwrap_err();
+        assert!(error.to_string().contains("ca_bundle_path"));
+
+        std::fs::write(&bundle, "not a certificate").unwrap();
+        let error = network_config(&config).http_client().unwrap_err();
+        assert!(format!("{:#}", error).contains("CA bundle"));
+    }
+
+    /// Test unset proxy settings fall back to the environment variables
+    #[test]
+    fn test_proxy_env_fallback() {
+        let env = |name: &str| match name {
+            "HTTP_PROXY" => Some("http://env-proxy:3128".to_string()),
+            "https_proxy" => Some("http://lower-proxy:3128".to_string()),
+            "NO_PROXY" => Some(String::new()),
+            _ => None,
+        };
+        let configured = ProxyConfig {
+            http: Some("http://corp-proxy:8080".to_string()),
+            ..Default::default()
+        };
+
+        let proxy = configured.with_env_fallback(env);
+        assert_eq!(proxy.http.as_deref(), Some("http://corp-proxy:8080"));
+        assert_eq!(proxy.https.as_deref(), Some("http://lower-proxy:3128"));
+        assert_eq!(proxy.no_proxy, None);
+        assert_eq!(
+            proxy.for_endpoint("https://s3.amazonaws.com"),
+            Some("http://lower-proxy:3128")
+        );
+        assert_eq!(
+            proxy.for_endpoint("http://minio:9000"),
+            Some("http://corp-proxy:8080")
+        );
+
+        let proxy = ProxyConfig::default().with_env_fallback(env);
+        assert_eq!(proxy.http.as_deref(), Some("http://env-proxy:3128"));
+    }
+
+    /// Test a folder that doesn't exist yet lists as empty on every provider,
+    /// while other errors still fail
+    #[test]
+    fn test_missing_folder_lists_empty() {
+        use reqwest::StatusCode;
+        use serde_json::json;
+
+        let not_found =
+            json!({ "error": { "code": "itemNotFound", "message": "Item does not exist" } });
+        assert!(parse_drive_list(StatusCode::NOT_FOUND, &not_found, "")
+            .unwrap()
+            .is_empty());
+        assert!(parse_onedrive_list(StatusCode::NOT_FOUND, &not_found, "")
+            .unwrap()
+            .is_empty());
+        assert!(
+            parse_box_list(StatusCode::NOT_FOUND, &json!({ "code": "not_found" }), "")
+                .unwrap()
+                .is_empty()
+        );
+        let dropbox = json!({ "error_summary": "path/not_found/..", "error": {} });
+        assert!(parse_dropbox_list(StatusCode::CONFLICT, &dropbox, "")
+            .unwrap()
+            .is_empty());
+        let pcloud = json!({ "result": 2005, "error": "Directory does not exist." });
+        assert!(parse_pcloud_list(&pcloud, "").unwrap().is_empty());
+        assert!(
+            parse_mega_ls(false, "", "[err:  ls: Couldn't find /Backups]", "")
+                .unwrap()
+                .is_empty()
+        );
+
+        let forbidden = json!({ "error": { "message": "Insufficient permissions" } });
+        assert!(parse_drive_list(StatusCode::FORBIDDEN, &forbidden, "").is_err());
+        assert!(parse_onedrive_list(StatusCode::UNAUTHORIZED, &forbidden, "").is_err());
+        let dropbox = json!({ "error_summary": "path/malformed_path/.." });
+        assert!(parse_dropbox_list(StatusCode::CONFLICT, &dropbox, "").is_err());
+        assert!(
+            parse_pcloud_list(&json!({ "result": 2000, "error": "Log in failed." }), "").is_err()
+        );
+        assert!(parse_mega_ls(false, "", "Not logged in", "").is_err());
+    }
+
+    /// Test existing folder listings keep only files matching the prefix
+    #[test]
+    fn test_folder_list_entries() {
+        use reqwest::StatusCode;
+        use serde_json::json;
+
+        let dropbox = json!({ "entries": [
+            { ".tag": "file", "name": "backup-full-1.tar.zst", "size": 42, "client_modified": "2024-01-01T12:00:00Z" },
+            { ".tag": "folder", "name": "backup-dir" },
+            { ".tag": "file", "name": "notes.txt", "size": 1 },
+        ] });
+        let items = parse_dropbox_list(StatusCode::OK, &dropbox, "backup-").unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].key, "backup-full-1.tar.zst");
+        assert_eq!(items[0].size, 42);
+        assert!(items[0].last_modified.is_some());
+
+        let pcloud = json!({ "result": 0, "metadata": { "contents": [
+            { "name": "backup-full-1.tar.zst", "isfolder": 0, "size": 7 },
+            { "name": "backup-old", "isfolder": 1 },
+        ] } });
+        assert_eq!(parse_pcloud_list(&pcloud, "backup-").unwrap().len(), 1);
+
+        let mega = "-rw- 1 1024 01Jan2024 12:00:00 backup-full-1.tar.zst\nd--- - - 01Jan2024 12:00:00 old\n";
+        let items = parse_mega_ls(true, mega, "", "backup-").unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].size, 1024);
+    }
+
+    /// Test uploads are labeled with the content type of their archive format
+    #[tokio::test]
+    async fn test_upload_content_type() {
+        assert_eq!(
+            content_type("backups/backup-full-1.tar.zst"),
+            "application/zstd"
+        );
+        assert_eq!(
+            content_type("backups/backup-full-1.tar.gz"),
+            "application/gzip"
+        );
+        assert_eq!(
+            content_type("backups/backup-full-1.tar.zst.json"),
+            "application/json"
+        );
+        assert_eq!(
+            content_type("backups/backup-full-1.tar.zst.001"),
+            "application/octet-stream"
+        );
+
+        use object_store::ObjectStore;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let store = std::sync::Arc::new(object_store::memory::InMemory::new());
+        let gcs = GCSProvider {
+            store: store.clone(),
+            bucket: "backups".to_string(),
+            metadata: BTreeMap::new(),
+        };
+        for (name, expected) in [
+            ("backup-full-1.tar.zst", "application/zstd"),
+            ("backup-full-1.tar.gz", "application/gzip"),
+        ] {
+            let file = temp_dir.path().join(name);
+            std::fs::write(&file, b"archive").unwrap();
+            let key = format!("backups/{}", name);
+            gcs.upload(&key, &file).await.unwrap();
+            let object = store
+                .get(&object_store::path::Path::from(key.as_str()))
+                .await
+                .unwrap();
+            assert_eq!(
+                object.attributes.get(&object_store::Attribute::ContentType),
+                Some(&expected.into())
+            );
+        }
+    }
+
+    /// Test the object store providers list a missing prefix as empty
+    #[tokio::test]
+    async fn test_object_store_missing_prefix() {
+        let store = std::sync::Arc::new(object_store::memory::InMemory::new());
+        let gcs = GCSProvider {
+            store: store.clone(),
+            bucket: "backups".to_string(),
+            metadata: BTreeMap::new(),
+        };
+        assert!(gcs.list("backups/").await.unwrap().is_empty());
+        let azure = AzureProvider {
+            store,
+            container: "backups".to_string(),
+            metadata: BTreeMap::new(),
+        };
+        assert!(azure.list("backups/").await.unwrap().is_empty());
+    }
+
+    /// Test a second MEGA provider resumes the session cached by the first
+    /// instead of logging in with the password again
+    #[tokio::test]
+    async fn test_mega_session_cached() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let log = temp_dir.path().join("calls");
+        let mega_cmd = temp_dir.path().join("mega-cmd");
+        std::fs::write(
+            &mega_cmd,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> {}\ncase \"$1\" in\n  whoami) exit 1 ;;\n  session) echo \"Your (secret) session is: SESSION123\" ;;\nesac\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&mega_cmd, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let session_file = temp_dir.path().join("config/zesty-backup/mega-session");
+        let provider = || MegaProvider {
+            email: "me@example.com".to_string(),
+            password: "secret".to_string(),
+            folder_path: None,
+            mega_cmd_path: Some(mega_cmd.to_string_lossy().to_string()),
+            session_file: Some(session_file.clone()),
+        };
+        let calls = || std::fs::read_to_string(&log).unwrap();
+
+        provider().ensure_logged_in().await.unwrap();
+        assert_eq!(calls(), "whoami\nlogin me@example.com secret\nsession\n");
+        let mode = std::fs::metadata(&session_file)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        std::fs::remove_file(&log).unwrap();
+        provider().ensure_logged_in().await.unwrap();
+        assert_eq!(calls(), "whoami\nlogin SESSION123\n");
+    }
+
+    /// Test a B2 bucket configured by name gets its id from b2_list_buckets,
+    /// and one configured by id gets its name
+    #[tokio::test]
+    async fn test_b2_bucket_resolution() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !String::from_utf8_lossy(&request).ends_with('}') {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let body = serde_json::json!({
+                "buckets": [{
+                    "accountId": "acct",
+                    "bucketId": "4a48fe8875c6214145260818",
+                    "bucketName": "my-backups",
+                    "bucketType": "allPrivate",
+                }]
+            })
+            .to_string();
+            socket
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        let mut provider = B2Provider {
+            account_id: "key-id".to_string(),
+            application_key: "key".to_string(),
+            bucket_id: String::new(),
+            bucket_name: "my-backups".to_string(),
+            authorized_account_id: "acct".to_string(),
+            api_url,
+            download_url: String::new(),
+            auth_token: Some("token".to_string()),
+            verify_sha1: true,
+            metadata: BTreeMap::new(),
+            http: reqwest::Client::new(),
+        };
+        provider.resolve_bucket().await.unwrap();
+        assert_eq!(provider.bucket_id, "4a48fe8875c6214145260818");
+        assert_eq!(provider.bucket_name, "my-backups");
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /b2api/v2/b2_list_buckets HTTP/1.1"));
+        assert!(request.contains("authorization: token"));
+        assert!(request.ends_with(r#"{"accountId":"acct","bucketName":"my-backups"}"#));
+
+        // Both known: no lookup (the server is gone, so one would fail)
+        provider.resolve_bucket().await.unwrap();
+
+        let response = serde_json::json!({
+            "buckets": [
+                { "bucketId": "id-1", "bucketName": "other" },
+                { "bucketId": "id-2", "bucketName": "my-backups" },
+            ]
+        });
+        let ok = reqwest::StatusCode::OK;
+        assert_eq!(
+            parse_b2_buckets(ok, &response, "id-2", "").unwrap(),
+            ("id-2".to_string(), "my-backups".to_string())
+        );
+        let error = parse_b2_buckets(ok, &response, "", "missing").unwrap_err();
+        assert!(error.to_string().contains("No B2 bucket named missing"));
+        let denied = serde_json::json!({ "code": "unauthorized", "message": "not allowed" });
+        assert!(
+            parse_b2_buckets(reqwest::StatusCode::UNAUTHORIZED, &denied, "id-2", "")
+                .unwrap_err()
+                .to_string()
+                .contains("not allowed")
+        );
+    }
+
+    /// Test transfer acceleration sends S3 traffic to the accelerate endpoint,
+    /// and a download endpoint takes over reads only
+    #[tokio::test]
+    async fn test_s3_transfer_acceleration() {
+        use tokio::io::AsyncReadExt;
+
+        let mut config = storage_config("aws", "eu-west-1");
+        assert_eq!(s3_endpoint(&config), "https://s3.eu-west-1.amazonaws.com");
+        config.s3_transfer_acceleration = true;
+        assert_eq!(s3_endpoint(&config), S3_ACCELERATE_ENDPOINT);
+        let mut wasabi = storage_config("wasabi", "eu-west-1");
+        wasabi.s3_transfer_acceleration = true;
+        let error = Provider::from_config(&wasabi).await.err().unwrap();
+        assert!(error.to_string().contains("only available on AWS S3"));
+
+        // The proxy sees which host each request is for in its CONNECT line
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_url = format!("http://{}", listener.local_addr().unwrap());
+        let (hosts_tx, mut hosts) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                let line = request.lines().next().unwrap_or_default().to_string();
+                if hosts_tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        config.access_key = "AKIDEXAMPLE".to_string();
+        config.secret_key = "secret".to_string();
+        config.proxy = Some(ProxyConfig {
+            https: Some(proxy_url),
+            ..Default::default()
+        });
+        config.download_endpoint = Some("https://downloads.example.com".to_string());
+        let provider = Provider::from_config(&config).await.unwrap();
+        let temp_dir = tempfile::TempDir::new().unwrap();
+
+        assert!(provider.head("backups/a.tar.zst").await.is_err());
+        assert_eq!(
+            hosts.recv().await.unwrap(),
+            "CONNECT test-bucket.s3-accelerate.amazonaws.com:443 HTTP/1.1"
+        );
+        while hosts.try_recv().is_ok() {}
+        assert!(provider
+            .download("backups/a.tar.zst", &temp_dir.path().join("a"))
+            .await
+            .is_err());
+        assert_eq!(
+            hosts.recv().await.unwrap(),
+            "CONNECT test-bucket.downloads.example.com:443 HTTP/1.1"
+        );
+    }
+
+    /// Test the provider wrapper tallies requests and bytes for a known
+    /// sequence of operations
+    #[tokio::test]
+    async fn test_traffic_counters() {
+        use tokio::io::AsyncReadExt;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file = temp_dir.path().join("a.tar.zst");
+        std::fs::write(&file, [0; 100]).unwrap();
+        let provider = Provider::from(Backend::Mock(Default::default()));
+
+        provider.upload("backups/a.tar.zst", &file).await.unwrap();
+        provider.upload("backups/b.tar.zst", &file).await.unwrap();
+        assert!(provider
+            .upload_if_absent("backups/a.tar.zst", &file)
+            .await
+            .is_err());
+        provider.list("backups/").await.unwrap();
+        provider.head("backups/a.tar.zst").await.unwrap();
+        provider
+            .download("backups/a.tar.zst", &temp_dir.path().join("out"))
+            .await
+            .unwrap();
+        let mut data = Vec::new();
+        let mut stream = provider.open_stream("backups/b.tar.zst").await.unwrap();
+        stream
+            .as_mut()
+            .unwrap()
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        provider.delete("backups/a.tar.zst").await.unwrap();
+
+        let traffic = provider.traffic();
+        assert_eq!(
+            traffic,
+            Traffic {
+                bytes_uploaded: 200,
+                bytes_downloaded: 200,
+                puts: 3,
+                gets: 2,
+                heads: 1,
+                lists: 1,
+                deletes: 1,
+            }
+        );
+        provider.list("").await.unwrap();
+        assert_eq!(
+            provider.traffic().since(&traffic),
+            Traffic {
+                lists: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    /// Test pCloud listings carry the modification time from its RFC 2822
+    /// dates, so retention can age pCloud backups
+    #[test]
+    fn test_parse_pcloud_list_modified() {
+        // Trimmed from a real listfolder response
+        let json = serde_json::json!({
+            "result": 0,
+            "metadata": {
+                "path": "/zesty-backups",
+                "name": "zesty-backups",
+                "created": "Mon, 04 Mar 2024 09:12:40 +0000",
+                "ismine": true,
+                "thumb": false,
+                "modified": "Thu, 21 Mar 2024 18:31:38 +0000",
+                "id": "d1820374926",
+                "isshared": false,
+                "icon": "folder",
+                "isfolder": true,
+                "parentfolderid": 0,
+                "folderid": 1820374926,
+                "contents": [
+                    {
+                        "name": "backup-full-20240321-183000.tar.zst",
+                        "created": "Thu, 21 Mar 2024 18:31:38 +0000",
+                        "thumb": false,
+                        "modified": "Thu, 21 Mar 2024 18:31:38 +0000",
+                        "isfolder": false,
+                        "fileid": 41823704671u64,
+                        "hash": 6234556717036738000u64,
+                        "comments": 0,
+                        "category": 0,
+                        "id": "f41823704671",
+                        "isshared": false,
+                        "ismine": true,
+                        "size": 1048576,
+                        "parentfolderid": 1820374926,
+                        "contenttype": "application/zstd",
+                        "icon": "file"
+                    },
+                    {
+                        "name": "old",
+                        "created": "Mon, 04 Mar 2024 09:12:40 +0000",
+                        "modified": "Mon, 04 Mar 2024 09:12:40 +0000",
+                        "isfolder": true,
+                        "folderid": 1820374999,
+                        "id": "d1820374999"
+                    }
+                ]
+            }
+        });
+        let items = parse_pcloud_list(&json, "").unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].key, "backup-full-20240321-183000.tar.zst");
+        assert_eq!(items[0].size, 1048576);
+        assert_eq!(
+            items[0].last_modified,
+            DateTime::from_timestamp(1711045898, 0)
+        );
+
+        assert_eq!(
+            parse_pcloud_time(&serde_json::json!(1711045898)),
+            DateTime::from_timestamp(1711045898, 0)
+        );
+        assert_eq!(parse_pcloud_time(&serde_json::json!("yesterday")), None);
+    }
+
+    /// Test [storage.tls] min_version reaches the client builder, defaulting to
+    /// TLS 1.2, and a minimum the clients can't enforce is rejected
+    #[test]
+    fn test_tls_min_version() {
+        let mut config = storage_config("b2", "");
+        let network = network_config(&config);
+        assert_eq!(network.tls.min_version, TlsVersion::Tls1_2);
+        assert_eq!(
+            network.tls.min_version().unwrap(),
+            reqwest::tls::Version::TLS_1_2
+        );
+        assert!(network.http_client().is_ok());
+
+        let tls: TlsOptions = toml::from_str("min_version = \"1.1\"").unwrap();
+        config.tls = Some(tls);
+        assert_eq!(
+            network_config(&config).tls.min_version().unwrap(),
+            reqwest::tls::Version::TLS_1_1
+        );
+
+        let tls: TlsOptions = toml::from_str("min_version = \"1.3\"").unwrap();
+        assert!(tls.validate().is_err());
+        config.tls = Some(tls);
+        let error = network_config(&config).http_client().unwrap_err();
+        assert!(
+            error.chain().any(|e| e.to_string().contains("min_version")),
+            "{:#}",
+            error
+        );
+        assert!(network_config(&config).client_options().is_err());
+        assert!(toml::from_str::<TlsOptions>("min_version = \"1.4\"").is_err());
+    }
+
+    /// Test B2 uploads send the data's SHA-1, or do_not_verify with
+    /// b2_verify_sha1 off
+    #[test]
+    fn test_b2_upload_sha1_header() {
+        let mut provider = B2Provider {
+            account_id: "key-id".to_string(),
+            application_key: "key".to_string(),
+            bucket_id: "bucket-id".to_string(),
+            bucket_name: "my-backups".to_string(),
+            authorized_account_id: "acct".to_string(),
+            api_url: String::new(),
+            download_url: String::new(),
+            auth_token: Some("token".to_string()),
+            verify_sha1: true,
+            metadata: BTreeMap::new(),
+            http: reqwest::Client::new(),
+        };
+        let content_sha1 = |provider: &B2Provider| {
+            let request = provider
+                .upload_request(
+                    "https://upload.example.com/b2_upload_file",
+                    "upload-token",
+                    "backups/a.tar.zst",
+                    b"hello".to_vec(),
+                )
+                .build()
+                .unwrap();
+            request.headers()["x-bz-content-sha1"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            content_sha1(&provider),
+            "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+        );
+
+        provider.verify_sha1 = false;
+        assert_eq!(content_sha1(&provider), "do_not_verify");
+    }
+
+    /// Test `[storage.metadata]` reaches the S3 `PutObject` request and is
+    /// checked against each provider's naming rules
+    #[tokio::test]
+    async fn test_storage_metadata() {
+        use object_store::ObjectStore;
+
+        let metadata = BTreeMap::from([
+            ("cost-center".to_string(), "cc-1234".to_string()),
+            ("classification".to_string(), "internal".to_string()),
+        ]);
+        let provider = S3Provider::new(
+            "https://s3.example.com",
+            None,
+            "us-east-1",
+            "my-backups",
+            "access",
+            "secret",
+            &NetworkConfig::default(),
+        )
+        .await
+        .unwrap()
+        .with_metadata(&metadata);
+        let request = provider.put_request("backups/a.tar.zst");
+        let sent = request.get_metadata().as_ref().unwrap();
+        assert_eq!(sent["cost-center"], "cc-1234");
+        assert_eq!(sent["classification"], "internal");
+
+        let store = std::sync::Arc::new(object_store::memory::InMemory::new());
+        let gcs = GCSProvider {
+            store: store.clone(),
+            bucket: "backups".to_string(),
+            metadata: metadata.clone(),
+        };
+        gcs.upload_bytes("backups/a.tar.zst", b"archive")
+            .await
+            .unwrap();
+        let object = store
+            .get(&object_store::path::Path::from("backups/a.tar.zst"))
+            .await
+            .unwrap();
+        assert_eq!(
+            object
+                .attributes
+                .get(&object_store::Attribute::Metadata("cost-center".into())),
+            Some(&"cc-1234".into())
+        );
+
+        let config = |provider: &str, name: &str, value: &str| StorageConfig {
+            metadata: BTreeMap::from([(name.to_string(), value.to_string())]),
+            ..storage_config(provider, "")
+        };
+        assert!(config("s3", "cost-center", "cc-1234")
+            .validate_metadata()
+            .is_ok());
+        assert!(config("s3", "cost center", "x")
+            .validate_metadata()
+            .is_err());
+        assert!(config("s3", "owner", "J\u{f6}rg")
+            .validate_metadata()
+            .is_err());
+        assert!(config("azure", "cost_center", "x")
+            .validate_metadata()
+            .is_ok());
+        assert!(config("azure", "cost-center", "x")
+            .validate_metadata()
+            .is_err());
+        assert!(config("azure", "1st", "x").validate_metadata().is_err());
+        assert!(config("b2", &"a".repeat(51), "x")
+            .validate_metadata()
+            .is_err());
+        assert!(config("gdrive", "owner", &"x".repeat(120))
+            .validate_metadata()
+            .is_err());
+        assert!(config("dropbox", "any name", "x")
+            .validate_metadata()
+            .is_ok());
+        assert_eq!(b2_info_value("a b/c%"), "a%20b/c%25");
+    }
+
+    /// Test S3 listing follows continuation tokens, stops on a truncated page
+    /// without one and fails on a repeated token
+    #[tokio::test]
+    async fn test_s3_list_pagination() {
+        use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output;
+        use aws_sdk_s3::types::Object;
+
+        let page = |key: &str, truncated: bool, token: Option<&str>| {
+            ListObjectsV2Output::builder()
+                .contents(Object::builder().key(key).size(1).build())
+                .is_truncated(truncated)
+                .set_next_continuation_token(token.map(str::to_string))
+                .build()
+        };
+        let list = |pages: Vec<(Option<&'static str>, ListObjectsV2Output)>| {
+            let pages = Mutex::new(pages.into_iter());
+            async move {
+                s3_list_pages(|token| {
+                    let (expected, page) = pages.lock().unwrap().next().expect("no more pages");
+                    assert_eq!(token.as_deref(), expected);
+                    async move { Ok(page) }
+                })
+                .await
+            }
+        };
+        let keys = |items: Vec<BackupItem>| items.into_iter().map(|i| i.key).collect::<Vec<_>>();
+
+        let items = list(vec![
+            (None, page("a", true, Some("t1"))),
+            (Some("t1"), page("b", true, Some("t2"))),
+            (Some("t2"), page("c", false, None)),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(keys(items), ["a", "b", "c"]);
+
+        // Truncated but without a token: stop instead of listing the first page again
+        let items = list(vec![
+            (None, page("a", true, Some("t1"))),
+            (Some("t1"), page("b", true, None)),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(keys(items), ["a", "b"]);
+
+        // A token on a page not marked truncated is still followed
+        let items = list(vec![
+            (None, page("a", false, Some("t1"))),
+            (Some("t1"), page("b", false, None)),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(keys(items), ["a", "b"]);
+
+        let err = list(vec![
+            (None, page("a", true, Some("t1"))),
+            (Some("t1"), page("b", true, Some("t2"))),
+            (Some("t2"), page("c", true, Some("t1"))),
+        ])
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("twice"));
+    }
+
+    /// Test a multipart upload resumes from the saved state after a part
+    /// fails, and is aborted and forgotten after a permanent failure
+    #[tokio::test]
+    async fn test_resumable_upload() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file = temp_dir.path().join("backup.tar.zst");
+        let data: Vec<u8> = (0..25u8).collect();
+        std::fs::write(&file, &data).unwrap();
+        let state = upload_state_path(&file);
+        let policy = retry::RetryPolicy {
+            max_attempts: 1,
+            base_delay: std::time::Duration::ZERO,
+            max_delay: std::time::Duration::ZERO,
+        };
+        let mock = mock::MockProvider::default();
+
+        mock.fail_part(2, false);
+        let err = resumable_upload(&mock, "backups/a", &file, 10, &policy)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("run it again to resume"));
+        assert!(state.exists());
+        assert!(mock.get("backups/a").is_none());
+
+        // The re-run only uploads the parts still missing
+        resumable_upload(&mock, "backups/a", &file, 10, &policy)
+            .await
+            .unwrap();
+        assert_eq!(mock.uploaded_parts(), [1, 2, 3]);
+        assert_eq!(mock.get("backups/a").unwrap(), data);
+        assert!(!state.exists());
+        assert!(mock.pending_uploads().is_empty());
+
+        // Retries cover a transient failure within one run
+        let retrying = retry::RetryPolicy {
+            max_attempts: 2,
+            ..policy
+        };
+        mock.fail_part(1, false);
+        resumable_upload(&mock, "backups/b", &file, 10, &retrying)
+            .await
+            .unwrap();
+        assert_eq!(mock.get("backups/b").unwrap(), data);
+
+        mock.fail_part(3, true);
+        assert!(resumable_upload(&mock, "backups/c", &file, 10, &retrying)
+            .await
+            .is_err());
+        assert!(!state.exists());
+        assert_eq!(mock.aborted().len(), 1);
+        assert!(mock.pending_uploads().is_empty());
+        assert!(mock.get("backups/c").is_none());
+    }
+
+    /// Test expired credentials and rate limits leave a multipart upload
+    /// resumable, while other client errors end it
+    #[test]
+    fn test_upload_step_failure_permanence() {
+        let permanent =
+            |status| is_permanent_upload_failure(&upload_step_failure(status, String::new()));
+        for status in [401, 403, 408, 429, 500, 503] {
+            assert!(!permanent(status), "{}", status);
+        }
+        for status in [400, 404, 413] {
+            assert!(permanent(status), "{}", status);
+        }
+    }
 }
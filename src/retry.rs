@@ -0,0 +1,260 @@
+use anyhow::Result;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// Never wait longer than this on a server-supplied Retry-After
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with full jitter: a random delay up to `base * 2^attempt`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let millis = rand::thread_rng().gen_range(0..=cap.as_millis() as u64);
+        Duration::from_millis(millis)
+    }
+}
+
+/// Fails fast for a cooldown window once a provider has failed `threshold`
+/// times in a row, so a throttled account isn't kept busy with requests.
+pub struct CircuitBreaker {
+    name: &'static str,
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str) -> Self {
+        Self::with_limits(name, 5, Duration::from_secs(60))
+    }
+
+    pub fn with_limits(name: &'static str, threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            name,
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Returns an error while the breaker is open.
+    pub fn check(&self) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(until) if Instant::now() < until => Err(anyhow::anyhow!(
+                "{} temporarily disabled after {} consecutive failures; retrying in {}s",
+                self.name,
+                state.consecutive_failures,
+                (until - Instant::now()).as_secs() + 1
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::default();
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// Parse a `Retry-After` header, given either as seconds or as an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                .to_std()
+                .unwrap_or(Duration::ZERO)
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Send a request with the default retry policy. `request` is called again for
+/// every attempt, since a request body can only be sent once.
+pub async fn send<F>(breaker: &CircuitBreaker, request: F) -> Result<Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    send_with_policy(breaker, &RetryPolicy::default(), request).await
+}
+
+/// Send a request, retrying rate limits, server errors and connection failures.
+/// A `Retry-After` header is honored; otherwise the wait is jittered backoff.
+/// The last response is returned as-is, so callers report errors the usual way.
+pub async fn send_with_policy<F>(
+    breaker: &CircuitBreaker,
+    policy: &RetryPolicy,
+    request: F,
+) -> Result<Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut attempt = 0;
+    loop {
+        breaker.check()?;
+        attempt += 1;
+        let last_attempt = attempt >= policy.max_attempts;
+
+        let delay = match request().send().await {
+            Ok(response) if !is_retryable(response.status()) => {
+                breaker.record_success();
+                return Ok(response);
+            }
+            Ok(response) => {
+                breaker.record_failure();
+                if last_attempt {
+                    return Ok(response);
+                }
+                let delay =
+                    retry_after(response.headers()).unwrap_or_else(|| policy.backoff(attempt));
+                warn!(
+                    "{} returned {}, retrying in {:.1}s",
+                    breaker.name,
+                    response.status(),
+                    delay.as_secs_f64()
+                );
+                delay
+            }
+            Err(e) => {
+                breaker.record_failure();
+                if last_attempt {
+                    return Err(e.into());
+                }
+                let delay = policy.backoff(attempt);
+                warn!(
+                    "{} request failed ({}), retrying in {:.1}s",
+                    breaker.name,
+                    e,
+                    delay.as_secs_f64()
+                );
+                delay
+            }
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve one canned response per connection, in order.
+    async fn serve(responses: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    /// Test a 429 with Retry-After waits that long before retrying
+    #[tokio::test]
+    async fn test_retry_after_is_honored() {
+        let url = serve(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        ])
+        .await;
+
+        let client = reqwest::Client::new();
+        let breaker = CircuitBreaker::new("test");
+        let started = Instant::now();
+        let response = send(&breaker, || client.get(&url)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    /// Test Retry-After parsing in both formats
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "7".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+
+        headers.insert(RETRY_AFTER, "86400".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(MAX_RETRY_AFTER));
+
+        let date = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        headers.insert(RETRY_AFTER, date.parse().unwrap());
+        let delay = retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+
+        headers.insert(RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    /// Test the breaker opens after repeated failures and closes on success
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::with_limits("test", 3, Duration::from_secs(60));
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+
+        breaker.record_success();
+        assert!(breaker.check().is_ok());
+
+        let breaker = CircuitBreaker::with_limits("test", 1, Duration::ZERO);
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+    }
+}
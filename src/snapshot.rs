@@ -0,0 +1,223 @@
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{info, warn};
+
+/// Name of the snapshot (ZFS) or snapshot volume (LVM) a backup reads from.
+const SNAPSHOT_NAME: &str = "zesty-backup";
+
+/// How the snapshot is taken.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotKind {
+    /// `btrfs subvolume snapshot -r` of a subvolume
+    Btrfs,
+    /// `zfs snapshot` of a dataset, read through its `.zfs/snapshot` directory
+    Zfs,
+    /// `lvcreate --snapshot` of a logical volume, mounted read-only
+    Lvm,
+    /// Only `create_command` and `delete_command`
+    Command,
+}
+
+/// A filesystem snapshot to back up from, so sources on a busy filesystem are
+/// archived as they were at one point in time.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct SnapshotConfig {
+    #[serde(rename = "type")]
+    pub kind: SnapshotKind,
+    pub path: String, // Mounted filesystem snapshotted; sources under it are read from the snapshot
+    pub mountpoint: Option<String>, // Where the snapshot is read (zfs default: <path>/.zfs/snapshot/zesty-backup)
+    pub dataset: Option<String>,    // zfs: dataset mounted at path, e.g. "tank/srv"
+    pub volume: Option<String>,     // lvm: logical volume mounted at path, e.g. "vg0/srv"
+    pub size: Option<String>,       // lvm: copy-on-write space for the snapshot (default: "1G")
+    pub create_command: Option<String>, // Replaces the built-in commands (run with sh -c)
+    pub delete_command: Option<String>, // Replaces the built-in teardown (run with sh -c)
+}
+
+/// A step that takes part of the snapshot, and the step that undoes it.
+struct Step {
+    create: Vec<String>,
+    delete: Vec<String>,
+}
+
+impl Step {
+    fn new(create: &[&str], delete: &[&str]) -> Self {
+        let argv = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+        Self {
+            create: argv(create),
+            delete: argv(delete),
+        }
+    }
+}
+
+impl SnapshotConfig {
+    /// Where the snapshot can be read once it's taken.
+    fn mountpoint(&self) -> Result<PathBuf> {
+        match (&self.mountpoint, self.kind) {
+            (Some(mountpoint), _) => Ok(PathBuf::from(mountpoint)),
+            (None, SnapshotKind::Zfs) => Ok(Path::new(&self.path)
+                .join(".zfs/snapshot")
+                .join(SNAPSHOT_NAME)),
+            (None, kind) => bail!("snapshot.mountpoint is required for {:?} snapshots", kind),
+        }
+    }
+
+    /// The commands that take the snapshot, in order.
+    fn steps(&self, mountpoint: &Path) -> Result<Vec<Step>> {
+        let mount = mountpoint.to_string_lossy();
+        let mut steps = match self.kind {
+            SnapshotKind::Btrfs => vec![Step::new(
+                &["btrfs", "subvolume", "snapshot", "-r", &self.path, &mount],
+                &["btrfs", "subvolume", "delete", &mount],
+            )],
+            SnapshotKind::Zfs => {
+                let dataset = self
+                    .dataset
+                    .as_deref()
+                    .context("snapshot.dataset is required for zfs snapshots")?;
+                let snapshot = format!("{}@{}", dataset, SNAPSHOT_NAME);
+                vec![Step::new(
+                    &["zfs", "snapshot", &snapshot],
+                    &["zfs", "destroy", &snapshot],
+                )]
+            }
+            SnapshotKind::Lvm => {
+                let volume = self
+                    .volume
+                    .as_deref()
+                    .context("snapshot.volume is required for lvm snapshots")?;
+                let (group, _) = volume
+                    .split_once('/')
+                    .context("snapshot.volume must be <volume group>/<logical volume>")?;
+                let snapshot = format!("{}/{}", group, SNAPSHOT_NAME);
+                let device = format!("/dev/{}", snapshot);
+                let size = self.size.as_deref().unwrap_or("1G");
+                vec![
+                    Step::new(
+                        &[
+                            "lvcreate",
+                            "--snapshot",
+                            "--size",
+                            size,
+                            "--name",
+                            SNAPSHOT_NAME,
+                            volume,
+                        ],
+                        &["lvremove", "-f", &snapshot],
+                    ),
+                    Step::new(&["mount", "-o", "ro", &device, &mount], &["umount", &mount]),
+                ]
+            }
+            SnapshotKind::Command => Vec::new(),
+        };
+        if self.create_command.is_some() || self.kind == SnapshotKind::Command {
+            let create = self
+                .create_command
+                .as_deref()
+                .context("snapshot.create_command is required for command snapshots")?;
+            let delete = self
+                .delete_command
+                .as_deref()
+                .context("snapshot.delete_command is required with create_command")?;
+            steps = vec![Step::new(&["sh", "-c", create], &["sh", "-c", delete])];
+        } else if let Some(ref delete) = self.delete_command {
+            // A custom teardown undoes every built-in step at once
+            for step in &mut steps {
+                step.delete.clear();
+            }
+            if let Some(last) = steps.last_mut() {
+                last.delete = vec!["sh".into(), "-c".into(), delete.clone()];
+            }
+        }
+        Ok(steps)
+    }
+}
+
+/// A snapshot that exists until this is dropped, which tears it down again
+/// whether or not the backup succeeded.
+pub struct Snapshot {
+    path: PathBuf,
+    mountpoint: PathBuf,
+    taken: Vec<Step>,
+}
+
+impl Snapshot {
+    /// Take the snapshot described by `config`. Returns `None`, after undoing
+    /// any part already taken, when the tools aren't installed or the
+    /// filesystem can't be snapshotted that way, so the backup goes on with
+    /// the live files.
+    pub fn create(config: &SnapshotConfig) -> Result<Option<Self>> {
+        let mountpoint = config.mountpoint()?;
+        let mut snapshot = Self {
+            path: PathBuf::from(&config.path),
+            mountpoint,
+            taken: Vec::new(),
+        };
+        info!(
+            "Taking {:?} snapshot of {}...",
+            config.kind,
+            snapshot.path.display()
+        );
+        for step in config.steps(&snapshot.mountpoint)? {
+            if let Err(e) = snapshot.run(&step.create) {
+                warn!(
+                    "Backing up the live files of {} without a snapshot: {:#}",
+                    snapshot.path.display(),
+                    e
+                );
+                return Ok(None);
+            }
+            snapshot.taken.push(step);
+        }
+        Ok(Some(snapshot))
+    }
+
+    /// Where a source path under the snapshotted filesystem is in the
+    /// snapshot, or `None` for paths outside it.
+    pub fn map(&self, source: &Path) -> Option<PathBuf> {
+        match source.strip_prefix(&self.path).ok()? {
+            relative if relative.as_os_str().is_empty() => Some(self.mountpoint.clone()),
+            relative => Some(self.mountpoint.join(relative)),
+        }
+    }
+
+    fn run(&self, argv: &[String]) -> Result<()> {
+        let Some((program, args)) = argv.split_first() else {
+            return Ok(());
+        };
+        let output = Command::new(program)
+            .args(args)
+            .env("ZESTY_SNAPSHOT_PATH", &self.path)
+            .env("ZESTY_SNAPSHOT_MOUNT", &self.mountpoint)
+            .output();
+        match output {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => bail!(
+                "{} failed: {}",
+                argv.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                bail!("{} is not installed", program)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to run {}", program)),
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if !self.taken.is_empty() {
+            info!("Removing snapshot of {}", self.path.display());
+        }
+        while let Some(step) = self.taken.pop() {
+            if let Err(e) = self.run(&step.delete) {
+                warn!("Failed to remove snapshot: {:#}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,483 @@
+use crate::archive::file_mode;
+use crate::incremental::WatermarkFilter;
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+/// Version control metadata directories, for `exclude_vcs`.
+pub const VCS_DIRS: [&str; 3] = [".git", ".hg", ".svn"];
+
+/// Whether an exclude pattern names a VCS directory (`.git`, `/.git/`, ...).
+pub fn is_vcs_pattern(pattern: &str) -> bool {
+    VCS_DIRS.contains(&pattern.trim_matches('/'))
+}
+
+/// Whether a file or directory name is hidden by the Unix dotfile convention.
+pub fn is_hidden(name: &OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
+/// A file a source hands to the archive: where it goes, its metadata and a
+/// reader for its contents.
+pub struct SourceEntry {
+    pub archive_path: String,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: u64,
+    pub reader: Box<dyn Read>,
+    /// The file on disk, if the entry comes from one
+    pub path: Option<PathBuf>,
+}
+
+/// A file a source found but couldn't stat or list: it goes in the archive's
+/// unreadable list rather than failing the backup.
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to read {archive_path}: {error}")]
+pub struct Unreadable {
+    pub archive_path: String,
+    pub error: io::Error,
+}
+
+/// Where the files of a backup come from. A source yields its files in the
+/// order they should be archived; what it leaves out by exclude pattern is
+/// counted in `excluded`.
+pub trait SourceProvider: Iterator<Item = Result<SourceEntry>> {
+    /// Files left out per exclude pattern, including patterns that matched nothing.
+    fn excluded(&self) -> BTreeMap<String, u64> {
+        BTreeMap::new()
+    }
+}
+
+/// A directory tree on the local filesystem, walked in file name order so
+/// archives of the same tree always list entries in the same order.
+pub struct LocalFsSource<'a> {
+    root: PathBuf,
+    read_root: PathBuf,
+    prefix: String,
+    walker: walkdir::IntoIter,
+    exclude: Vec<String>,
+    include_hidden: bool,
+    exclude_vcs: Option<bool>,
+    gitignore: Option<GitignoreRules>,
+    skip_dir: Option<PathBuf>,
+    dereference_root: bool,
+    watermark: Option<&'a mut WatermarkFilter>,
+    excluded: BTreeMap<String, u64>,
+    hidden: u64,
+    vcs: u64,
+    gitignored: u64,
+}
+
+impl<'a> LocalFsSource<'a> {
+    /// Archive the tree at `root` as `<prefix>/<root name>/...`.
+    pub fn new(root: &Path, prefix: &str) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            read_root: root.to_path_buf(),
+            prefix: prefix.to_string(),
+            walker: walk(root, false),
+            exclude: Vec::new(),
+            include_hidden: true,
+            exclude_vcs: None,
+            gitignore: None,
+            skip_dir: None,
+            dereference_root: false,
+            watermark: None,
+            excluded: BTreeMap::new(),
+            hidden: 0,
+            vcs: 0,
+            gitignored: 0,
+        }
+    }
+
+    /// Read the files from `path`, a copy of the root such as a snapshot of
+    /// it, archiving and filtering them as if they were read from the root.
+    pub fn read_from(mut self, path: Option<&Path>) -> Self {
+        let Some(path) = path else {
+            return self;
+        };
+        self.read_root = path.to_path_buf();
+        self.walker = walk(path, self.dereference_root);
+        if self.gitignore.is_some() {
+            self.gitignore = Some(GitignoreRules::new(path));
+        }
+        self
+    }
+
+    /// When the root is a symlink, walk the directory it points to (archived
+    /// under the symlink's name). Symlinks below the root are unaffected.
+    pub fn dereference_root(mut self, dereference_root: bool) -> Self {
+        self.dereference_root = dereference_root;
+        self.walker = walk(&self.read_root, dereference_root);
+        self
+    }
+
+    /// Leave out paths containing any of `patterns`.
+    pub fn exclude(mut self, patterns: &[String]) -> Self {
+        self.exclude = patterns.to_vec();
+        self.excluded = patterns.iter().map(|p| (p.clone(), 0)).collect();
+        self
+    }
+
+    /// Whether dotfiles below the root are included.
+    pub fn include_hidden(mut self, include_hidden: bool) -> Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    /// Leave VCS directories out (`Some(true)`), keep them regardless of
+    /// hidden and `.gitignore` rules (`Some(false)`) or treat them like any
+    /// other directory (`None`).
+    pub fn exclude_vcs(mut self, exclude_vcs: Option<bool>) -> Self {
+        self.exclude_vcs = exclude_vcs;
+        self
+    }
+
+    /// Leave out what `.gitignore` files in the tree ignore.
+    pub fn respect_gitignore(mut self, respect_gitignore: bool) -> Self {
+        self.gitignore = respect_gitignore.then(|| GitignoreRules::new(&self.read_root));
+        self
+    }
+
+    /// Leave out a directory below the root and everything in it.
+    pub fn skip_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.skip_dir = dir;
+        self
+    }
+
+    /// Only take files past the watermark.
+    pub fn watermark(mut self, watermark: Option<&'a mut WatermarkFilter>) -> Self {
+        self.watermark = watermark;
+        self
+    }
+
+    /// Log how many entries the hidden, VCS and `.gitignore` rules left out.
+    pub fn log_skipped(&self) {
+        let root = self.root.display();
+        if self.hidden > 0 {
+            info!("Skipped {} hidden entries in {}", self.hidden, root);
+        }
+        if self.vcs > 0 {
+            info!("Skipped {} VCS directories in {}", self.vcs, root);
+        }
+        if self.gitignored > 0 {
+            info!(
+                "Skipped {} entries ignored by .gitignore in {}",
+                self.gitignored, root
+            );
+        }
+    }
+
+    /// Whether an entry below the root is pruned along with everything under
+    /// it. Hidden and VCS entries are pruned before excludes apply; the root
+    /// was asked for by name.
+    fn prune(&mut self, entry: &walkdir::DirEntry) -> bool {
+        if self.skip_dir.as_deref() == Some(self.logical_path(entry.path()).as_path()) {
+            return true;
+        }
+        let is_vcs =
+            entry.file_type().is_dir() && VCS_DIRS.iter().any(|dir| entry.file_name() == *dir);
+        match (is_vcs, self.exclude_vcs) {
+            (true, Some(true)) => {
+                self.vcs += 1;
+                true
+            }
+            (true, Some(false)) => false,
+            _ => {
+                if !self.include_hidden && is_hidden(entry.file_name()) {
+                    self.hidden += 1;
+                    return true;
+                }
+                let ignored = self.gitignore.as_mut().is_some_and(|rules| {
+                    rules.is_ignored(entry.path(), entry.file_type().is_dir())
+                });
+                self.gitignored += ignored as u64;
+                ignored
+            }
+        }
+    }
+
+    /// The file an entry refers to, unless it's excluded, a directory or
+    /// behind the watermark.
+    fn entry(&mut self, entry: &walkdir::DirEntry) -> Result<Option<SourceEntry>> {
+        let read_path = entry.path();
+        let logical_path = self.logical_path(read_path);
+        let entry_path = logical_path.as_path();
+
+        // Count excluded files against the first pattern that matched
+        let exclude_pattern = self
+            .exclude
+            .iter()
+            .find(|pattern| entry_path.to_string_lossy().contains(pattern.as_str()));
+        if let Some(pattern) = exclude_pattern {
+            if !entry.file_type().is_dir() {
+                *self.excluded.entry(pattern.clone()).or_insert(0) += 1;
+            }
+            return Ok(None);
+        }
+
+        // Directories are implied by their files
+        if read_path.is_dir() {
+            return Ok(None);
+        }
+
+        // Symlinks are archived as the file they point to; a dangling one, or
+        // a file deleted since the walk, is unreadable
+        let metadata = match fs::metadata(read_path) {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                return Err(Unreadable {
+                    archive_path: self.archive_path(entry_path),
+                    error,
+                }
+                .into())
+            }
+        };
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok());
+        if let Some(ref mut watermark) = self.watermark {
+            let name = entry_path.strip_prefix(&self.root).unwrap_or(entry_path);
+            let mtime = mtime.map_or(0, |d| d.as_nanos() as u64);
+            if !watermark.admit(&name.to_string_lossy(), mtime) {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(SourceEntry {
+            archive_path: self.archive_path(entry_path),
+            size: metadata.len(),
+            mode: file_mode(&metadata),
+            mtime: mtime.map_or(0, |d| d.as_secs()),
+            reader: Box::new(LazyFile::new(read_path)),
+            path: Some(read_path.to_path_buf()),
+        }))
+    }
+
+    /// The name in the archive of a path below the root.
+    fn archive_path(&self, entry_path: &Path) -> String {
+        let relative_path = entry_path
+            .strip_prefix(self.root.parent().unwrap_or(&self.root))
+            .or_else(|_| entry_path.strip_prefix(&self.root))
+            .unwrap_or(entry_path);
+        if self.prefix.is_empty() {
+            relative_path.to_string_lossy().to_string()
+        } else {
+            format!("{}/{}", self.prefix, relative_path.to_string_lossy())
+        }
+    }
+
+    /// Where a path read from `read_root` is below the root.
+    fn logical_path(&self, read_path: &Path) -> PathBuf {
+        match read_path.strip_prefix(&self.read_root) {
+            Ok(relative) if self.read_root != self.root && !relative.as_os_str().is_empty() => {
+                self.root.join(relative)
+            }
+            Ok(_) if self.read_root != self.root => self.root.clone(),
+            _ => read_path.to_path_buf(),
+        }
+    }
+}
+
+impl Iterator for LocalFsSource<'_> {
+    type Item = Result<SourceEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.walker.next()? {
+                Ok(entry) => entry,
+                // Below the root, a directory that can't be listed is unreadable
+                Err(e) if e.depth() > 0 && e.path().is_some() => {
+                    let path = self.logical_path(e.path().unwrap());
+                    let archive_path = self.archive_path(&path);
+                    let error = match e.into_io_error() {
+                        Some(error) => error,
+                        None => io::Error::other("filesystem loop"),
+                    };
+                    return Some(Err(Unreadable {
+                        archive_path,
+                        error,
+                    }
+                    .into()));
+                }
+                Err(e) => return Some(Err(e).context("Failed to read directory entry")),
+            };
+            if entry.depth() > 0 && self.prune(&entry) {
+                if entry.file_type().is_dir() {
+                    self.walker.skip_current_dir();
+                }
+                continue;
+            }
+            match self.entry(&entry) {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl SourceProvider for LocalFsSource<'_> {
+    fn excluded(&self) -> BTreeMap<String, u64> {
+        self.excluded.clone()
+    }
+}
+
+/// A walk of the tree at `root` in file name order, not following symlinks
+/// except, with `dereference_root`, `root` itself.
+fn walk(root: &Path, dereference_root: bool) -> walkdir::IntoIter {
+    WalkDir::new(root)
+        .follow_links(false)
+        .follow_root_links(dereference_root)
+        .sort_by_file_name()
+        .into_iter()
+}
+
+/// A file opened on first read, so files the archive skips unread (unchanged
+/// in mtime mode) are never opened and only one file is open at a time.
+struct LazyFile {
+    path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl LazyFile {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            file: None,
+        }
+    }
+}
+
+impl Read for LazyFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.file.is_none() {
+            self.file = Some(fs::File::open(&self.path)?);
+        }
+        self.file.as_mut().expect("file was just opened").read(buf)
+    }
+}
+
+/// The `.gitignore` rules of a backed-up tree for `respect_gitignore`, read
+/// per directory as the walk reaches it. As in git, the nearest `.gitignore`
+/// with a matching rule decides, so a nested `!pattern` re-includes a path.
+struct GitignoreRules {
+    root: PathBuf,
+    dirs: HashMap<PathBuf, Option<ignore::gitignore::Gitignore>>,
+}
+
+impl GitignoreRules {
+    fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// The rules of `dir/.gitignore`, if it has one that parses.
+    fn rules(&mut self, dir: &Path) -> Option<&ignore::gitignore::Gitignore> {
+        self.dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let file = dir.join(".gitignore");
+                if !file.is_file() {
+                    return None;
+                }
+                let mut builder = ignore::gitignore::GitignoreBuilder::new(dir);
+                if let Some(e) = builder.add(&file) {
+                    warn!("Ignoring part of {}: {}", file.display(), e);
+                }
+                builder
+                    .build()
+                    .inspect_err(|e| warn!("Ignoring {}: {}", file.display(), e))
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        for dir in path.ancestors().skip(1) {
+            if let Some(rules) = self.rules(dir) {
+                match rules.matched(path, is_dir) {
+                    ignore::Match::Ignore(_) => return true,
+                    ignore::Match::Whitelist(_) => return false,
+                    ignore::Match::None => {}
+                }
+            }
+            if dir == self.root {
+                break;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Test a local source yields the files of a tree in order, leaving out
+    /// hidden and excluded files
+    #[test]
+    fn test_local_fs_source_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("app");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::write(root.join("README.md"), b"readme").unwrap();
+        fs::write(root.join("src/main.rs"), b"fn main() {}").unwrap();
+        fs::write(root.join("target/debug/app"), b"binary").unwrap();
+        fs::write(root.join(".env"), b"SECRET=1").unwrap();
+
+        let patterns = ["target/".to_string(), "*.log".to_string()];
+        let mut source = LocalFsSource::new(&root, "project")
+            .exclude(&patterns)
+            .include_hidden(false);
+        let entries: Vec<(String, u64, Vec<u8>)> = source
+            .by_ref()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let mut contents = Vec::new();
+                entry.reader.read_to_end(&mut contents).unwrap();
+                (entry.archive_path, entry.size, contents)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("project/app/README.md".to_string(), 6, b"readme".to_vec()),
+                (
+                    "project/app/src/main.rs".to_string(),
+                    12,
+                    b"fn main() {}".to_vec()
+                ),
+            ]
+        );
+        assert_eq!(
+            source.excluded(),
+            BTreeMap::from([("*.log".to_string(), 0), ("target/".to_string(), 1)])
+        );
+        assert_eq!(source.hidden, 1);
+
+        // Without a prefix, entries start at the root's name
+        let names: Vec<String> = LocalFsSource::new(&root, "")
+            .map(|entry| entry.unwrap().archive_path)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "app/.env",
+                "app/README.md",
+                "app/src/main.rs",
+                "app/target/debug/app"
+            ]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::process::{Child, Command, Stdio};
+
+/// Connection settings for `ssh://` sources. Authentication goes through the
+/// system `ssh`, so keys from the SSH agent and `~/.ssh/config` work as usual.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct SshConfig {
+    pub user: Option<String>,          // Default user when the URL has none
+    pub port: Option<u16>,             // Default port when the URL has none
+    pub identity_file: Option<String>, // Private key (default: agent / ssh config)
+    pub options: Option<Vec<String>>,  // Extra -o options, e.g. "StrictHostKeyChecking=yes"
+    pub command: Option<String>,       // ssh binary (default: "ssh")
+}
+
+/// A source path of the form `ssh://[user@]host[:port]/path`.
+#[derive(Debug, PartialEq)]
+pub struct SshSource {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: String,
+}
+
+impl SshSource {
+    /// Returns `None` for paths that aren't `ssh://` URLs.
+    pub fn parse(source: &str) -> Option<Result<Self>> {
+        let rest = source.strip_prefix("ssh://")?;
+        Some(Self::parse_url(rest).with_context(|| format!("Invalid SSH source: {}", source)))
+    }
+
+    fn parse_url(rest: &str) -> Result<Self> {
+        let (authority, path) = rest
+            .split_once('/')
+            .context("Expected ssh://[user@]host[:port]/path")?;
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user.to_string()), host_port),
+            None => (None, authority),
+        };
+        let (host, port) = match host_port.split_once(':') {
+            Some((host, port)) => (host, Some(port.parse().context("Invalid SSH port")?)),
+            None => (host_port, None),
+        };
+        if host.is_empty() {
+            return Err(anyhow::anyhow!("Missing SSH host"));
+        }
+        // ssh would read these as options (e.g. -oProxyCommand=...)
+        if host.starts_with('-') || user.as_deref().is_some_and(|user| user.starts_with('-')) {
+            return Err(anyhow::anyhow!("SSH host and user can't start with '-'"));
+        }
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return Err(anyhow::anyhow!("Missing SSH path"));
+        }
+
+        Ok(Self {
+            user,
+            host: host.to_string(),
+            port,
+            path: format!("/{}", path),
+        })
+    }
+
+    /// The remote directory `tar` runs in and the name it archives, so entries
+    /// come out as `<name>/...` like local directories do.
+    pub fn parent_and_name(&self) -> (&str, &str) {
+        match self.path.rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some((parent, name)) => (parent, name),
+            None => ("/", self.path.as_str()),
+        }
+    }
+
+    /// Start `tar` on the remote host, writing the archive to the child's stdout.
+    pub fn spawn_tar(&self, config: &SshConfig) -> Result<Child> {
+        let (parent, name) = self.parent_and_name();
+        let remote_command = format!("tar -cf - -C {} {}", shell_quote(parent), shell_quote(name));
+
+        let mut cmd = Command::new(config.command.as_deref().unwrap_or("ssh"));
+        // Never prompt for a password: a daemon has no one to answer it
+        cmd.arg("-o").arg("BatchMode=yes");
+        if let Some(port) = self.port.or(config.port) {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        if let Some(ref identity_file) = config.identity_file {
+            cmd.arg("-i").arg(identity_file);
+        }
+        for option in config.options.iter().flatten() {
+            cmd.arg("-o").arg(option);
+        }
+        let destination = match self.user.as_ref().or(config.user.as_ref()) {
+            Some(user) => format!("{}@{}", user, self.host),
+            None => self.host.clone(),
+        };
+        // Nothing after "--" is taken as an option
+        cmd.arg("--").arg(destination).arg(remote_command);
+
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run ssh to {}", self.host))
+    }
+}
+
+/// Quote an argument for the remote POSIX shell.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test parsing ssh:// source URLs
+    #[test]
+    fn test_parse_ssh_source() {
+        assert!(SshSource::parse("/var/www").is_none());
+
+        let source = SshSource::parse("ssh://deploy@web1:2222/var/www/app/")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            source,
+            SshSource {
+                user: Some("deploy".to_string()),
+                host: "web1".to_string(),
+                port: Some(2222),
+                path: "/var/www/app".to_string(),
+            }
+        );
+        assert_eq!(source.parent_and_name(), ("/var/www", "app"));
+
+        let source = SshSource::parse("ssh://db.internal/etc").unwrap().unwrap();
+        assert_eq!(source.user, None);
+        assert_eq!(source.port, None);
+        assert_eq!(source.parent_and_name(), ("/", "etc"));
+
+        assert!(SshSource::parse("ssh://host").unwrap().is_err());
+        assert!(SshSource::parse("ssh://host/").unwrap().is_err());
+        assert!(SshSource::parse("ssh://host:port/etc").unwrap().is_err());
+        assert!(SshSource::parse("ssh://-oProxyCommand=touch%20x/etc")
+            .unwrap()
+            .is_err());
+        assert!(SshSource::parse("ssh://-l@host/etc").unwrap().is_err());
+    }
+}